@@ -0,0 +1,23 @@
+// Digit helpers shared by the days that work on the decimal digits of numbers
+
+// The amount of decimal digits in n, counted without going through a string
+pub fn digit_length(n: impl Into<u128>) -> u32 {
+    let mut n = n.into();
+    let mut length = 1;
+
+    while n >= 10 {
+        n /= 10;
+        length += 1;
+    }
+
+    length
+}
+
+#[test]
+fn test_digit_length() {
+    assert_eq!(digit_length(0_u64), 1);
+    assert_eq!(digit_length(9_u64), 1);
+    assert_eq!(digit_length(10_u64), 2);
+    assert_eq!(digit_length(u64::MAX), 20);
+    assert_eq!(digit_length(u128::MAX), u128::MAX.to_string().len() as u32);
+}
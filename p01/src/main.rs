@@ -1,59 +1,439 @@
 use std::fs;
+use std::env;
 use std::collections::HashMap;
 
+type Column = Vec<u32>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    SortedL1,
+    SortedL2,
+    Similarity,
+    Jaccard,
+}
+
+impl Metric {
+    fn from_name(name: &str) -> Option<Metric> {
+        match name {
+            "l1" => Some(Metric::SortedL1),
+            "l2" => Some(Metric::SortedL2),
+            "similarity" => Some(Metric::Similarity),
+            "jaccard" => Some(Metric::Jaccard),
+            _ => None
+        }
+    }
+
+    fn compare(&self, l: &[u32], m: &[u32]) -> f64 {
+        match self {
+            Metric::SortedL1 => total_distance(l, m) as f64,
+            Metric::SortedL2 => euclidean_distance(l, m),
+            Metric::Similarity => similarity_score(l, m) as f64,
+            Metric::Jaccard => jaccard_index(l, m),
+        }
+    }
+}
+
 fn main() {
-    let (mut l, mut m) = parse("input");
+    let columns = parse("input");
+
+    match env::args().nth(1) {
+        Some(mode) if mode == "stream" => {
+            let edits = parse_edits(&env::args().nth(2).expect("No edits file given"));
+            let limit = columns
+                .iter()
+                .flatten()
+                .chain(edits.iter().map(|(_, _, value)| value))
+                .max()
+                .map_or(1, |m| m + 1);
+
+            let mut tracker = Tracker::new(&columns[0], &columns[1], limit);
+
+            for (edit, list, value) in edits {
+                match edit {
+                    Edit::Insert => tracker.insert(list, value),
+                    Edit::Remove => { tracker.remove(list, value); }
+                }
 
-    println!("p1 {}", total_distance(&mut l, &mut m));
-    println!("p2 {}", similarity_score(&l, &m));
+                let distance = tracker.distance().map_or("-".to_string(), |d| d.to_string());
+                println!("{} {}", distance, tracker.similarity());
+            }
+        },
+        Some(name) => {
+            let metric = Metric::from_name(&name)
+                .unwrap_or_else(|| panic!("Unknown metric {}", name));
+            let pair: Vec<usize> = env::args()
+                .skip(2)
+                .map(|c| c.parse::<usize>().unwrap())
+                .collect();
+
+            if let [a, b] = pair[..] {
+                println!("{} {} {}", a, b, metric.compare(&columns[a], &columns[b]));
+                return
+            }
+
+            for (a, b, value) in pairwise(&columns, metric) {
+                println!("{} {} {}", a, b, value);
+            }
+        },
+        None => {
+            println!("p1 {}", total_distance(&columns[0], &columns[1]));
+            println!("p2 {}", similarity_score(&columns[0], &columns[1]));
+        }
+    }
 }
 
-fn parse(input: &'static str) -> (Vec<u32>, Vec<u32>) {
-    let mut k = vec![];
-    let mut l = vec![];
+fn parse(input: &str) -> Vec<Column> {
+    let mut columns: Vec<Column> = vec![];
 
     for line in fs::read_to_string(input).unwrap().lines() {
-        let (ns, ms) = line.split_once("   ").unwrap();
-        let n = ns.parse::<u32>().unwrap();
-        let m = ms.parse::<u32>().unwrap();
-        k.push(n);
-        l.push(m);
+        for (i, n) in line.split_whitespace().enumerate() {
+            if i == columns.len() {
+                columns.push(vec![]);
+            }
+
+            columns[i].push(n.parse::<u32>().unwrap());
+        }
     }
 
-    (k, l)
+    columns
+}
+
+#[test]
+fn test_parse_columns() {
+    let columns = parse("test_input_2");
+
+    assert_eq!(columns.len(), 3);
+    assert_eq!(columns[2], vec![1, 2, 3, 3, 5, 3]);
+}
+
+fn pairwise(columns: &[Column], metric: Metric) -> Vec<(usize, usize, f64)> {
+    let mut results = vec![];
+
+    for a in 0..columns.len() {
+        for b in (a + 1)..columns.len() {
+            results.push((a, b, metric.compare(&columns[a], &columns[b])));
+        }
+    }
+
+    results
+}
+
+#[test]
+fn test_pairwise() {
+    let columns = parse("test_input_2");
+    let results = pairwise(&columns, Metric::SortedL1);
+
+    assert_eq!(results, vec![(0, 1, 11.0), (0, 2, 1.0), (1, 2, 10.0)])
 }
 
-fn total_distance(l: &mut Vec<u32>, m: &mut Vec<u32>) -> u32 {
-    l.sort();
-    m.sort();
+fn sorted(list: &[u32]) -> Column {
+    let mut list = list.to_vec();
+    list.sort();
+    list
+}
 
-    (0..l.len())
-        .map(|i| l[i].abs_diff(m[i]))
+fn total_distance(l: &[u32], m: &[u32]) -> u64 {
+    sorted(l)
+        .iter()
+        .zip(sorted(m).iter())
+        .map(|(a, b)| a.abs_diff(*b) as u64)
         .sum()
 }
 
 #[test]
 fn test_distance() {
-    let (mut v1, mut v2) = parse("test_input");
+    let columns = parse("test_input");
+
+    assert_eq!(total_distance(&columns[0], &columns[1]), 11)
+}
+
+fn euclidean_distance(l: &[u32], m: &[u32]) -> f64 {
+    let squares: u64 = sorted(l)
+        .iter()
+        .zip(sorted(m).iter())
+        .map(|(a, b)| (a.abs_diff(*b) as u64).pow(2))
+        .sum();
 
-    assert_eq!(total_distance(&mut v1, &mut v2), 11)
+    (squares as f64).sqrt()
 }
 
-fn similarity_score(list: &Vec<u32>, m: &Vec<u32>) -> u32 {
-    let mut h: HashMap<u32, u32> = HashMap::new();
-    for i in m.iter() {
+#[test]
+fn test_euclidean_distance() {
+    let columns = parse("test_input");
+
+    assert_eq!(Metric::SortedL2.compare(&columns[0], &columns[1]), 35_f64.sqrt())
+}
+
+fn counts(list: &[u32]) -> HashMap<u32, u64> {
+    let mut h: HashMap<u32, u64> = HashMap::new();
+    for i in list.iter() {
         h.entry(*i).and_modify(|c| *c += 1).or_insert(1);
     }
+    h
+}
+
+fn similarity_score(list: &[u32], m: &[u32]) -> u64 {
+    let h = counts(m);
 
     list
         .iter()
-        .map(|j| j * h.get(j).unwrap_or(&0))
+        .map(|j| *j as u64 * h.get(j).unwrap_or(&0))
         .sum()
 }
 
 #[test]
 fn test_similarity_score() {
-    let (v1, v2) = parse("test_input");
+    let columns = parse("test_input");
+
+    assert_eq!(similarity_score(&columns[0], &columns[1]), 31)
+}
+
+// Jaccard over multisets: the shared counts divided by the combined counts.
+fn jaccard_index(l: &[u32], m: &[u32]) -> f64 {
+    let lc = counts(l);
+    let mc = counts(m);
+    let mut shared = 0;
+    let mut combined = 0;
+
+    for (value, &n) in lc.iter() {
+        let k = *mc.get(value).unwrap_or(&0);
+        shared += n.min(k);
+        combined += n.max(k);
+    }
+
+    for (value, &k) in mc.iter() {
+        if !lc.contains_key(value) {
+            combined += k;
+        }
+    }
+
+    if combined == 0 {
+        return 1.0
+    }
+
+    shared as f64 / combined as f64
+}
+
+#[test]
+fn test_jaccard_index() {
+    let columns = parse("test_input");
+
+    assert_eq!(Metric::from_name("jaccard"), Some(Metric::Jaccard));
+    assert_eq!(Metric::Jaccard.compare(&columns[0], &columns[1]), 0.5)
+}
+
+#[derive(Debug, PartialEq)]
+enum Edit {
+    Insert,
+    Remove,
+}
+
+fn parse_edits(input: &str) -> Vec<(Edit, usize, u32)> {
+    fs::read_to_string(input).unwrap().lines().map(|line| {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let edit = match parts[0] {
+            "+" => Edit::Insert,
+            "-" => Edit::Remove,
+            _ => panic!("Invalid edit {}", line)
+        };
+
+        (edit, parts[1].parse().unwrap(), parts[2].parse().unwrap())
+    }).collect()
+}
+
+// The sorted L1 distance between two equally long lists is the same as
+// summing |#{l <= t} - #{m <= t}| over every t. That difference is kept in
+// blocks of roughly sqrt(limit) values, where each block keeps a lazy offset
+// and a histogram of its values, so an edit only touches O(sqrt(limit)) blocks.
+struct Block {
+    start: usize,
+    end: usize,
+    lazy: i64,
+    histogram: HashMap<i64, u64>,
+    nonneg: u64,
+    sum: u64
+}
+
+impl Block {
+    fn new(start: usize, end: usize, diff: &[i64]) -> Block {
+        let mut block = Block {
+            start,
+            end,
+            lazy: 0,
+            histogram: HashMap::new(),
+            nonneg: 0,
+            sum: 0
+        };
+        block.rebuild(diff);
+        block
+    }
+
+    fn rebuild(&mut self, diff: &[i64]) {
+        self.histogram.clear();
+        self.nonneg = 0;
+        self.sum = 0;
+
+        for d in &diff[self.start..self.end] {
+            *self.histogram.entry(*d).or_default() += 1;
+            self.sum += d.unsigned_abs();
+            if *d >= 0 {
+                self.nonneg += 1;
+            }
+        }
+    }
+
+    fn count(&self, value: i64) -> u64 {
+        *self.histogram.get(&(value - self.lazy)).unwrap_or(&0)
+    }
+
+    fn shift(&mut self, delta: i64) {
+        let len = (self.end - self.start) as u64;
+
+        if delta > 0 {
+            self.sum = self.sum + self.nonneg - (len - self.nonneg);
+            self.lazy += 1;
+            self.nonneg += self.count(0);
+        } else {
+            let positive = self.nonneg - self.count(0);
+            self.sum = self.sum - positive + (len - positive);
+            self.lazy -= 1;
+            self.nonneg = positive;
+        }
+    }
+}
+
+struct Tracker {
+    counts: [HashMap<u32, u64>; 2],
+    diff: Vec<i64>,
+    blocks: Vec<Block>,
+    lengths: [usize; 2],
+    similarity: u64
+}
+
+impl Tracker {
+    fn new(l: &[u32], m: &[u32], limit: u32) -> Tracker {
+        let size = limit as usize;
+        let block_size = (size as f64).sqrt().ceil().max(1.0) as usize;
+        let mut diff = vec![0; size];
+
+        for v in l {
+            diff[*v as usize] += 1;
+        }
+
+        for v in m {
+            diff[*v as usize] -= 1;
+        }
+
+        for t in 1..size {
+            diff[t] += diff[t - 1];
+        }
+
+        let blocks = (0..size)
+            .step_by(block_size)
+            .map(|start| Block::new(start, (start + block_size).min(size), &diff))
+            .collect();
+
+        Tracker {
+            counts: [counts(l), counts(m)],
+            diff,
+            blocks,
+            lengths: [l.len(), m.len()],
+            similarity: similarity_score(l, m)
+        }
+    }
+
+    fn insert(&mut self, list: usize, value: u32) {
+        let other = self.counts[1 - list].get(&value).unwrap_or(&0);
+        self.similarity += value as u64 * other;
+        *self.counts[list].entry(value).or_default() += 1;
+        self.lengths[list] += 1;
+        self.add(value, if list == 0 { 1 } else { -1 });
+    }
+
+    fn remove(&mut self, list: usize, value: u32) -> bool {
+        match self.counts[list].get_mut(&value) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => return false
+        }
+
+        let other = self.counts[1 - list].get(&value).unwrap_or(&0);
+        self.similarity -= value as u64 * other;
+        self.lengths[list] -= 1;
+        self.add(value, if list == 0 { -1 } else { 1 });
+        true
+    }
+
+    // Adds delta to the difference for every t >= value
+    fn add(&mut self, value: u32, delta: i64) {
+        let from = value as usize;
+        if from >= self.diff.len() {
+            panic!("Value {} is outside of the tracked limit", value);
+        }
+
+        for block in self.blocks.iter_mut() {
+            if block.end <= from {
+                continue
+            }
+
+            if block.start >= from {
+                block.shift(delta);
+                continue
+            }
+
+            for t in block.start..block.end {
+                self.diff[t] += block.lazy;
+                if t >= from {
+                    self.diff[t] += delta;
+                }
+            }
+            block.lazy = 0;
+            block.rebuild(&self.diff);
+        }
+    }
+
+    // The sum over the blocks only matches the sorted L1 distance while both
+    // lists are equally long, so there is no distance in between
+    fn distance(&self) -> Option<u64> {
+        if self.lengths[0] != self.lengths[1] {
+            return None
+        }
+
+        Some(self.blocks.iter().map(|b| b.sum).sum())
+    }
+
+    fn similarity(&self) -> u64 {
+        self.similarity
+    }
+}
+
+#[test]
+fn test_tracker() {
+    let columns = parse("test_input");
+    let mut tracker = Tracker::new(&columns[0], &columns[1], 10);
+
+    assert_eq!(tracker.distance(), Some(11));
+    assert_eq!(tracker.similarity(), 31);
+
+    let mut lists = [columns[0].clone(), columns[1].clone()];
+
+    for (edit, list, value) in parse_edits("test_edits") {
+        let changed = match edit {
+            Edit::Insert => { tracker.insert(list, value); true },
+            Edit::Remove => tracker.remove(list, value)
+        };
+        assert!(changed);
+
+        match edit {
+            Edit::Insert => lists[list].push(value),
+            Edit::Remove => { lists[list].remove(lists[list].iter().position(|&v| v == value).unwrap()); }
+        }
+
+        let [l, m] = &lists;
+        let expected = (l.len() == m.len()).then(|| total_distance(l, m));
+        assert_eq!(tracker.distance(), expected);
+        assert_eq!(tracker.similarity(), similarity_score(l, m));
+    }
 
-    assert_eq!(similarity_score(&v1, &v2), 31)
+    assert_eq!(tracker.distance(), Some(total_distance(&lists[0], &lists[1])));
+    assert!(!tracker.remove(0, 1));
 }
@@ -1,13 +1,75 @@
 use std::fs;
+use std::env;
+
+type Report = Vec<u32>;
+
+struct Tolerance {
+    min_step: u32,
+    max_step: u32,
+    removals: usize
+}
+
+impl Tolerance {
+    fn new(min_step: u32, max_step: u32, removals: usize) -> Tolerance {
+        Tolerance { min_step, max_step, removals }
+    }
+
+    fn allows(&self, l: u32, r: u32, increasing: bool) -> bool {
+        let step = r.abs_diff(l);
+        (r > l) == increasing && step >= self.min_step && step <= self.max_step
+    }
+}
 
 fn main() {
     let l = parse("input");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let format = match args.first().map(|a| a.as_str()) {
+        Some("csv") | Some("table") => Some(args.remove(0)),
+        _ => None
+    };
+    let args: Vec<usize> = args
+        .iter()
+        .map(|a| a.parse::<usize>().unwrap())
+        .collect();
+
+    let tolerance = match args[..] {
+        [min_step, max_step, removals] => {
+            Tolerance::new(min_step as u32, max_step as u32, removals)
+        },
+        [] => Tolerance::new(1, 3, 1),
+        _ => panic!("Invalid tolerance {:?}, expected min max removals", args)
+    };
+
+    if let Some(format) = format {
+        let diagnoses: Vec<Diagnosis> = l
+            .iter()
+            .map(|report| diagnose(report, &tolerance))
+            .collect();
 
-    println!("p1 {}", safety_clearance(&l));
-    println!("p2 {}", safety_clearance_dampened(&l));
+        if format == "csv" {
+            print!("{}", csv(&diagnoses));
+        } else {
+            print!("{}", table(&diagnoses));
+        }
+        return
+    }
+
+    if !args.is_empty() {
+        for (i, report) in l.iter().enumerate() {
+            if let Some(removed) = dampen(report, &tolerance) {
+                println!("{} {:?}", i, removed);
+            }
+        }
+
+        println!("safe {}", safety_clearance(&l, &tolerance));
+        return
+    }
+
+    println!("p1 {}", safety_clearance(&l, &Tolerance::new(1, 3, 0)));
+    println!("p2 {}", safety_clearance(&l, &tolerance));
 }
 
-fn parse(input: &'static str) -> Vec<Vec<u32>> {
+fn parse(input: &'static str) -> Vec<Report> {
     fs::read_to_string(input)
         .unwrap()
         .lines()
@@ -22,48 +84,273 @@ fn parse(input: &'static str) -> Vec<Vec<u32>> {
 #[test]
 fn test_safety_clearance() {
     let l = parse("test_input");
-    assert_eq!(safety_clearance(&l), 2)
+    assert_eq!(safety_clearance(&l, &Tolerance::new(1, 3, 0)), 2)
 }
 
-fn safety_clearance(l: &Vec<Vec<u32>>) -> usize {
-    l.iter().filter(|n| is_safe(*n)).count()
+fn safety_clearance(l: &[Report], tolerance: &Tolerance) -> usize {
+    l.iter().filter(|n| dampen(n, tolerance).is_some()).count()
 }
 
 #[test]
 fn test_safety_clearance_dampened() {
     let l = parse("test_input");
-    assert_eq!(safety_clearance_dampened(&l), 4)
+    assert_eq!(safety_clearance(&l, &Tolerance::new(1, 3, 1)), 4)
+}
+
+// Returns the smallest set of indices to remove to make the report safe,
+// if there is one within the allowed amount of removals.
+fn dampen(report: &[u32], tolerance: &Tolerance) -> Option<Vec<usize>> {
+    [true, false]
+        .iter()
+        .filter_map(|&increasing| dampen_direction(report, tolerance, increasing))
+        .min_by_key(|removed| removed.len())
+}
+
+// costs[i] is the least amount of removals needed to keep level i as the
+// last level. Only the k + 1 levels before i can precede it, which keeps
+// this at O(n * k).
+fn dampen_direction(report: &[u32], tolerance: &Tolerance, increasing: bool) -> Option<Vec<usize>> {
+    let n = report.len();
+    let k = tolerance.removals;
+    let mut costs: Vec<Option<usize>> = vec![None; n];
+    let mut previous: Vec<Option<usize>> = vec![None; n];
+
+    if n == 0 {
+        return Some(vec![])
+    }
+
+    for i in 0..n {
+        if i <= k {
+            costs[i] = Some(i);
+        }
+
+        for p in i.saturating_sub(k + 1)..i {
+            let Some(cost) = costs[p] else { continue };
+            let cost = cost + (i - p - 1);
+
+            if cost > k || !tolerance.allows(report[p], report[i], increasing) {
+                continue
+            }
+
+            if costs[i].is_none_or(|c| cost < c) {
+                costs[i] = Some(cost);
+                previous[i] = Some(p);
+            }
+        }
+    }
+
+    let last = (n.saturating_sub(k + 1)..n)
+        .filter(|&i| costs[i].is_some_and(|c| c + (n - 1 - i) <= k))
+        .min_by_key(|&i| costs[i].unwrap() + (n - 1 - i))?;
+
+    let mut kept = vec![false; n];
+    let mut i = Some(last);
+    while let Some(j) = i {
+        kept[j] = true;
+        i = previous[j];
+    }
+
+    Some((0..n).filter(|&i| !kept[i]).collect())
+}
+
+#[test]
+fn test_dampen() {
+    let l = parse("test_input");
+    let tolerance = Tolerance::new(1, 3, 1);
+
+    assert_eq!(dampen(&l[0], &tolerance), Some(vec![]));
+    assert_eq!(dampen(&l[1], &tolerance), None);
+    assert_eq!(dampen(&l[3], &tolerance), Some(vec![2]));
+    assert_eq!(dampen(&l[4], &tolerance), Some(vec![3]));
+
+    let tolerance = Tolerance::new(1, 3, 2);
+    assert_eq!(dampen(&[1, 9, 2, 3, 9, 4], &tolerance), Some(vec![1, 4]));
+    assert_eq!(dampen(&[9, 1, 2, 3, 4, 0], &tolerance), Some(vec![0, 5]));
+
+    let tolerance = Tolerance::new(2, 5, 0);
+    assert_eq!(dampen(&[1, 3, 8, 10], &tolerance), Some(vec![]));
+    assert_eq!(dampen(&l[0], &tolerance), None);
 }
 
-fn safety_clearance_dampened(l: &Vec<Vec<u32>>) -> usize {
-    l.iter().filter(|n| is_safe(*n) || is_safe_minus_one(*n)).count()
+#[derive(Debug, PartialEq)]
+enum Direction {
+    Increasing,
+    Decreasing,
+    Mixed
 }
 
-// This is not the most memory efficient way of doing this, but
-// considering how fast this still is... I don't really feel
-// the need to optimize it all that much.
-fn is_safe_minus_one(report: &Vec<u32>) -> bool {
-    (0..report.len()).any(|j| {
-        let mut q = report.clone();
-        q.remove(j);
-        is_safe(&q)
-    })
+#[derive(Debug, PartialEq)]
+enum Violation {
+    Flat,
+    TooSmall,
+    TooLarge,
+    DirectionChange
 }
 
-fn is_safe(report: &Vec<u32>) -> bool {
-    let end = report.len() - 1;
-    let all_increasing = (0..end).all(|i| {
-        let l = report[i];
-        let r = report[i + 1];
-        l > r && (l - r) <= 3
-    });
+struct Diagnosis {
+    direction: Direction,
+    violation: Option<(usize, usize, Violation)>,
+    removed: Option<Vec<usize>>
+}
 
-    let all_decreasing = (0..end).all(|i| {
-        let l = report[i];
-        let r = report[i + 1];
-        l < r && (r - l) <= 3
+impl Diagnosis {
+    fn safe(&self) -> bool {
+        self.removed.is_some()
+    }
+
+    fn rescued(&self) -> bool {
+        self.violation.is_some() && self.safe()
+    }
+}
+
+fn diagnose(report: &[u32], tolerance: &Tolerance) -> Diagnosis {
+    let steps: Vec<(u32, u32)> = report.windows(2).map(|w| (w[0], w[1])).collect();
+    let ups = steps.iter().filter(|(l, r)| r > l).count();
+    let downs = steps.iter().filter(|(l, r)| r < l).count();
+
+    let direction = if ups > 0 && downs == 0 {
+        Direction::Increasing
+    } else if downs > 0 && ups == 0 {
+        Direction::Decreasing
+    } else {
+        Direction::Mixed
+    };
+
+    // A mixed report is expected to keep going the way its first step went
+    let increasing = steps
+        .iter()
+        .find(|(l, r)| l != r)
+        .is_none_or(|(l, r)| r > l);
+
+    let violation = steps.iter().enumerate().find_map(|(i, &(l, r))| {
+        let step = r.abs_diff(l);
+        let kind = if step == 0 {
+            Violation::Flat
+        } else if (r > l) != increasing {
+            Violation::DirectionChange
+        } else if step > tolerance.max_step {
+            Violation::TooLarge
+        } else if step < tolerance.min_step {
+            Violation::TooSmall
+        } else {
+            return None
+        };
+
+        Some((i, i + 1, kind))
     });
 
-    return all_increasing || all_decreasing
+    Diagnosis { direction, violation, removed: dampen(report, tolerance) }
+}
+
+#[test]
+fn test_diagnose() {
+    let l = parse("test_input");
+    let tolerance = Tolerance::new(1, 3, 1);
+    let diagnoses: Vec<Diagnosis> = l
+        .iter()
+        .map(|report| diagnose(report, &tolerance))
+        .collect();
+
+    assert_eq!(diagnoses[0].direction, Direction::Decreasing);
+    assert_eq!(diagnoses[0].violation, None);
+    assert_eq!(diagnoses[1].violation, Some((1, 2, Violation::TooLarge)));
+    assert!(!diagnoses[1].safe());
+    assert_eq!(diagnoses[3].direction, Direction::Mixed);
+    assert_eq!(diagnoses[3].violation, Some((1, 2, Violation::DirectionChange)));
+    assert!(diagnoses[3].rescued());
+    assert_eq!(diagnoses[4].violation, Some((2, 3, Violation::Flat)));
+    assert_eq!(diagnoses[4].removed, Some(vec![3]));
 }
 
+fn columns(index: usize, diagnosis: &Diagnosis) -> [String; 7] {
+    let (pair, kind) = match &diagnosis.violation {
+        Some((l, r, kind)) => (format!("{}-{}", l, r), format!("{:?}", kind)),
+        None => (String::new(), String::new())
+    };
+    let removed = match &diagnosis.removed {
+        Some(removed) => removed
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<String>>()
+            .join(" "),
+        None => String::new()
+    };
+
+    [
+        index.to_string(),
+        format!("{:?}", diagnosis.direction),
+        pair,
+        kind,
+        diagnosis.safe().to_string(),
+        diagnosis.rescued().to_string(),
+        removed
+    ]
+}
+
+const HEADER: [&str; 7] = [
+    "report", "direction", "violation", "kind", "safe", "rescued", "removed"
+];
+
+fn csv(diagnoses: &[Diagnosis]) -> String {
+    let mut out = HEADER.join(",") + "\n";
+
+    for (i, diagnosis) in diagnoses.iter().enumerate() {
+        out += &columns(i, diagnosis).join(",");
+        out += "\n";
+    }
+
+    out
+}
+
+#[test]
+fn test_csv() {
+    let l = parse("test_input");
+    let tolerance = Tolerance::new(1, 3, 1);
+    let diagnoses: Vec<Diagnosis> = l
+        .iter()
+        .map(|report| diagnose(report, &tolerance))
+        .collect();
+    let out = csv(&diagnoses);
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(lines[0], "report,direction,violation,kind,safe,rescued,removed");
+    assert_eq!(lines[1], "0,Decreasing,,,true,false,");
+    assert_eq!(lines[2], "1,Increasing,1-2,TooLarge,false,false,");
+    assert_eq!(lines[4], "3,Mixed,1-2,DirectionChange,true,true,2");
+}
+
+fn table(diagnoses: &[Diagnosis]) -> String {
+    let rows: Vec<[String; 7]> = diagnoses
+        .iter()
+        .enumerate()
+        .map(|(i, diagnosis)| columns(i, diagnosis))
+        .collect();
+
+    let widths: Vec<usize> = (0..HEADER.len())
+        .map(|c| {
+            rows
+                .iter()
+                .map(|row| row[c].len())
+                .chain([HEADER[c].len()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let line = |cells: Vec<&str>| -> String {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, w)| format!("{:<w$}", cell, w = w))
+            .collect();
+
+        format!("| {} |\n", padded.join(" | "))
+    };
+
+    let mut out = line(HEADER.to_vec());
+    for row in rows.iter() {
+        out += &line(row.iter().map(|c| c.as_str()).collect());
+    }
+
+    out
+}
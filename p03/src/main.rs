@@ -1,53 +1,281 @@
 use std::fs;
-use std::ops::Range;
-use regex::Regex;
+use std::env;
+
+// Returns None when the result doesn't fit in 64 bits
+type Handler = fn(&[i64]) -> Option<i64>;
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Call(String, Vec<i64>),
+    Do,
+    Dont
+}
+
+#[derive(Debug, PartialEq)]
+struct Step {
+    offset: usize,
+    token: Token,
+    accepted: bool,
+    overflowed: bool
+}
+
+struct Instruction {
+    name: &'static str,
+    arity: Option<usize>,
+    max_digits: usize,
+    handler: Handler
+}
+
+struct Registry {
+    instructions: Vec<Instruction>
+}
+
+impl Registry {
+    fn new() -> Registry {
+        Registry { instructions: vec![] }
+    }
+
+    // The puzzle's own instruction set: mul with two arguments of 1-3 digits
+    fn aoc() -> Registry {
+        let mut registry = Registry::new();
+        registry.register("mul", Some(2), 3, product);
+        registry
+    }
+
+    fn extended() -> Registry {
+        let mut registry = Registry::new();
+        registry.register("mul", None, 3, product);
+        registry.register("add", None, 3, |args| args.iter().try_fold(0_i64, |a, &b| a.checked_add(b)));
+        registry.register("sub", Some(2), 3, |args| args[0].checked_sub(args[1]));
+        registry
+    }
+
+    // An arity of None accepts any amount of arguments (at least one)
+    fn register(&mut self, name: &'static str, arity: Option<usize>, max_digits: usize, handler: Handler) {
+        self.instructions.push(Instruction { name, arity, max_digits, handler });
+    }
+
+    fn get(&self, name: &str) -> &Instruction {
+        self.instructions.iter().find(|i| i.name == name).unwrap()
+    }
+}
+
+fn product(args: &[i64]) -> Option<i64> {
+    args.iter().try_fold(1_i64, |a, &b| a.checked_mul(b))
+}
+
+struct Tokenizer<'a> {
+    input: &'a [u8],
+    registry: &'a Registry,
+    pos: usize
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str, registry: &'a Registry) -> Tokenizer<'a> {
+        Tokenizer { input: input.as_bytes(), registry, pos: 0 }
+    }
+
+    fn literal(&mut self, word: &str) -> bool {
+        if self.input[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            return true
+        }
+
+        false
+    }
+
+    fn number(&mut self, max_digits: usize) -> Option<i64> {
+        let start = self.pos;
+        while self.pos < self.input.len() && self.input[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+
+        if start == self.pos || self.pos - start > max_digits {
+            return None
+        }
+
+        std::str::from_utf8(&self.input[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn call(&mut self, instruction: &Instruction) -> Option<Token> {
+        if !self.literal(instruction.name) || !self.literal("(") {
+            return None
+        }
+
+        let mut args = vec![self.number(instruction.max_digits)?];
+        while self.literal(",") {
+            args.push(self.number(instruction.max_digits)?);
+        }
+
+        if !self.literal(")") || instruction.arity.is_some_and(|n| n != args.len()) {
+            return None
+        }
+
+        Some(Token::Call(instruction.name.to_string(), args))
+    }
+
+    // Tries to read a token at the current position, leaving the position
+    // untouched when nothing matches.
+    fn token(&mut self) -> Option<Token> {
+        let start = self.pos;
+
+        if self.literal("do()") {
+            return Some(Token::Do)
+        }
+
+        if self.literal("don't()") {
+            return Some(Token::Dont)
+        }
+
+        for instruction in self.registry.instructions.iter() {
+            if let Some(token) = self.call(instruction) {
+                return Some(token)
+            }
+
+            self.pos = start;
+        }
+
+        None
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = (usize, Token);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.input.len() {
+            let start = self.pos;
+
+            if let Some(token) = self.token() {
+                return Some((start, token))
+            }
+
+            self.pos += 1;
+        }
+
+        None
+    }
+}
 
 fn main() {
     let shopkeeper_data = fs::read_to_string("input").unwrap();
-    let skips = parse_skips(&shopkeeper_data);
-    println!("p1 {}", multiply(&shopkeeper_data, &vec![]));
-    println!("p2 {}", multiply(&shopkeeper_data, &skips));
-}
+    let args: Vec<String> = env::args().skip(1).collect();
+    let registry = if args.iter().any(|a| a == "extended") {
+        Registry::extended()
+    } else {
+        Registry::aoc()
+    };
+
+    if args.iter().any(|a| a == "trace") {
+        let (_, trace) = interpret(&shopkeeper_data, &registry, true);
 
-fn parse_skips(input: &String) -> Vec<Range<usize>> {
-    let skips_re = Regex::new(r"don't\(\).*do\(\)").unwrap();
-    skips_re
-        .captures_iter(&input)
-        .map(|caps| caps.get(0).unwrap().range())
-        .collect()
+        for step in trace {
+            let state = if step.overflowed {
+                "overflowed"
+            } else if step.accepted {
+                "accepted"
+            } else {
+                "skipped"
+            };
+            println!("{} {:?} {}", step.offset, step.token, state);
+        }
+        return
+    }
+
+    println!("p1 {}", multiply(&shopkeeper_data, &registry, false));
+    println!("p2 {}", multiply(&shopkeeper_data, &registry, true));
 }
 
-fn multiply(input: &String, skips: &Vec<Range<usize>>) -> u32 {
-    let re = Regex::new(r"mul\((\d+),(\d+)\)").unwrap();
+fn interpret(input: &str, registry: &Registry, conditionals: bool) -> (i64, Vec<Step>) {
+    let mut enabled = true;
+    let mut total: i64 = 0;
+    let mut trace = vec![];
+
+    for (offset, token) in Tokenizer::new(input, registry) {
+        let mut overflowed = false;
+        let accepted = match &token {
+            Token::Do | Token::Dont => {
+                enabled = token == Token::Do || !conditionals;
+                conditionals
+            },
+            // A call whose result or whose addition to the total overflows
+            // is left out
+            Token::Call(name, args) => {
+                if enabled {
+                    match (registry.get(name).handler)(args).and_then(|v| total.checked_add(v)) {
+                        Some(t) => total = t,
+                        None => overflowed = true
+                    }
+                }
+                enabled && !overflowed
+            }
+        };
+
+        trace.push(Step { offset, token, accepted, overflowed });
+    }
+
+    (total, trace)
+}
 
-    re
-        .captures_iter(&input)
-        .filter(|caps| {
-            let n = caps.get(0).unwrap().range();
+fn multiply(input: &str, registry: &Registry, conditionals: bool) -> i64 {
+    let (total, _) = interpret(input, registry, conditionals);
+    total
+}
 
-            !skips.iter().any(|r| r.start <= n.end && n.start <= r.end)
-        })
-        .map(|caps| {
-            let (_, [ls, rs]) = caps.extract();
-            let left = ls.parse::<u32>().unwrap();
-            let right = rs.parse::<u32>().unwrap();
+#[test]
+fn test_tokenizer() {
+    let tokens: Vec<(usize, Token)> = Tokenizer::new("mul(4*mul(6,9!do()mul(2,3)don't()", &Registry::aoc()).collect();
 
-            left * right
-        })
-        .sum()
+    assert_eq!(tokens, vec![(14, Token::Do), (18, Token::Call("mul".to_string(), vec![2, 3])), (26, Token::Dont)]);
 }
 
 #[test]
 fn test_multiply_input() {
     let shopkeeper_data = fs::read_to_string("1").unwrap();
 
-    assert_eq!(multiply(&shopkeeper_data, &vec![]), 161);
+    assert_eq!(multiply(&shopkeeper_data, &Registry::aoc(), false), 161);
 }
 
 #[test]
 fn test_multiply_input_with_skips() {
     let shopkeeper_data = fs::read_to_string("2").unwrap();
-    let skips = parse_skips(&shopkeeper_data);
 
-    assert_eq!(multiply(&shopkeeper_data, &skips), 48);
+    assert_eq!(multiply(&shopkeeper_data, &Registry::aoc(), true), 48);
+}
+
+#[test]
+fn test_multiply_across_lines() {
+    let shopkeeper_data = fs::read_to_string("3").unwrap();
+    let (total, trace) = interpret(&shopkeeper_data, &Registry::aoc(), true);
+
+    assert_eq!(total, 59);
+    assert_eq!(trace.iter().filter(|s| !s.accepted).count(), 3);
+    assert_eq!(trace[2], Step { offset: 15, token: Token::Call("mul".to_string(), vec![3, 4]), accepted: false, overflowed: false });
+}
+
+#[test]
+fn test_extended_instructions() {
+    let shopkeeper_data = fs::read_to_string("4").unwrap();
+
+    assert_eq!(multiply(&shopkeeper_data, &Registry::aoc(), false), 6);
+    assert_eq!(multiply(&shopkeeper_data, &Registry::extended(), false), 6 + 24 + 10 - 7 + 997_002_999);
+    assert_eq!(multiply(&shopkeeper_data, &Registry::extended(), true), 6 + 24 + 10 - 7);
+
+    let mut registry = Registry::new();
+    registry.register("pow", Some(2), 2, |args| args[0].checked_pow(args[1] as u32));
+    assert_eq!(multiply("pow(2,10)pow(2,100)pow(10,3)", &registry, false), 2024);
+}
+
+#[test]
+fn test_overflowing_instructions() {
+    let (total, trace) = interpret("mul(999,999,999,999,999,999,999)add(1,2)", &Registry::extended(), false);
+    assert_eq!(total, 3);
+    assert!(trace[0].overflowed && !trace[0].accepted);
+    assert!(trace[1].accepted);
+
+    let input = "mul(999,999,999,999,999,999)".repeat(10);
+    let (total, trace) = interpret(&input, &Registry::extended(), false);
+    assert_eq!(total, 9 * 999_i64.pow(6));
+    assert_eq!(trace.iter().filter(|s| s.overflowed).count(), 1);
+    assert!(trace[9].overflowed);
 }
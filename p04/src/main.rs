@@ -1,4 +1,6 @@
 use std::fs;
+use std::env;
+use std::collections::{HashMap, VecDeque};
 
 const TRANSLATIONS: [Point; 8] = [
     (-1, -1), // TOP LEFT
@@ -11,19 +13,8 @@ const TRANSLATIONS: [Point; 8] = [
     (1, 1)    // BOTTOM RIGHT
 ];
 
-const CORNERS: [Point; 4] = [
-    (-1, -1), // TOP LEFT
-    (-1, 1),  // TOP RIGHT
-    (1, -1),  // BOTTOM LEFT
-    (1, 1)    // BOTTOM RIGHT
-];
-
-const VALID_WORDS: [&'static str; 4] = [
-    "MSMS",
-    "SSMM",
-    "MMSS",
-    "SMSM",
-];
+// In shapes a '.' matches any character
+const X_MAS: &str = "M.S\n.A.\nM.S";
 
 type RawGrid = Vec<Vec<char>>;
 type Point = (isize, isize);
@@ -42,7 +33,7 @@ impl Grid {
         Grid {vector, ylen, xlen}
     }
 
-    fn out_of_bounds(&self, x: isize, y: isize) -> bool {
+    fn out_of_bounds(&self, y: isize, x: isize) -> bool {
         y < 0 || x < 0 || y >= self.ylen || x >= self.xlen
     }
 
@@ -53,6 +44,27 @@ impl Grid {
 
 fn main() {
     let grid = parse("input");
+    let words: Vec<String> = env::args().skip(1).collect();
+
+    if words.first().is_some_and(|w| w == "shape") {
+        let shape = fs::read_to_string(&words[1]).unwrap();
+
+        for (point, orientation) in match_shape(&grid, &shape) {
+            println!("{:?} {}", point, orientation);
+        }
+        return
+    }
+
+    if !words.is_empty() {
+        let matches = find_words(&grid, &words);
+
+        for m in matches.iter() {
+            println!("{} {:?} {:?}", m.word, m.start, m.direction);
+        }
+        print!("{}", render(&grid, &matches));
+        return
+    }
+
     println!("p1 {}", xmas_count(&grid));
     println!("p2 {}", x_mas_count(&grid));
 }
@@ -65,38 +77,172 @@ fn parse(input: &'static str) -> Grid {
     Grid::new(vector)
 }
 
-fn xmas_count(grid: &Grid) -> u32 {
-    (0..grid.ylen).map(|y| {
-        (0..grid.xlen).map(|x| {
-            count_xmasses(grid, &(y, x))
-        }).sum::<u32>()
-    }).sum()
+#[derive(Debug, PartialEq)]
+struct Match {
+    word: String,
+    start: Point,
+    direction: Point
+}
+
+// Aho-Corasick automaton, so every word in the dictionary is found in a
+// single pass over each line of the grid.
+struct Automaton {
+    words: Vec<Vec<char>>,
+    edges: Vec<HashMap<char, usize>>,
+    fail: Vec<usize>,
+    output: Vec<Vec<usize>>
 }
 
-fn count_xmasses(grid: &Grid, point: &Point) -> u32 {
-    let mut count = 0;
+impl Automaton {
+    fn new(words: &[String]) -> Automaton {
+        let mut automaton = Automaton {
+            words: words.iter().map(|w| w.chars().collect()).collect(),
+            edges: vec![HashMap::new()],
+            fail: vec![0],
+            output: vec![vec![]]
+        };
+
+        for (i, word) in words.iter().enumerate() {
+            let mut state = 0;
+            for c in word.chars() {
+                state = match automaton.edges[state].get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        automaton.edges.push(HashMap::new());
+                        automaton.fail.push(0);
+                        automaton.output.push(vec![]);
+                        let next = automaton.edges.len() - 1;
+                        automaton.edges[state].insert(c, next);
+                        next
+                    }
+                };
+            }
+            automaton.output[state].push(i);
+        }
+
+        let mut deq: VecDeque<usize> = automaton.edges[0].values().copied().collect();
+
+        while let Some(state) = deq.pop_front() {
+            let edges: Vec<(char, usize)> = automaton.edges[state]
+                .iter()
+                .map(|(&c, &next)| (c, next))
+                .collect();
+
+            for (c, next) in edges {
+                let fail = automaton.step(automaton.fail[state], c);
+                automaton.fail[next] = fail;
 
-    for (dy, dx) in &TRANSLATIONS {
-        let mut word = String::new();
+                let inherited = automaton.output[fail].clone();
+                automaton.output[next].extend(inherited);
+                deq.push_back(next);
+            }
+        }
+
+        automaton
+    }
 
-        for i in 0..4 {
-            let ddy = (dy * i) + point.0;
-            let ddx = (dx * i) + point.1;
+    fn step(&self, mut state: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.edges[state].get(&c) {
+                return next
+            }
 
-            if grid.out_of_bounds(ddy, ddx) {
-                continue
+            if state == 0 {
+                return 0
             }
 
-            let c = grid.get(ddy, ddx);
-            word.push(c);
+            state = self.fail[state];
         }
+    }
+}
+
+fn find_words(grid: &Grid, words: &[String]) -> Vec<Match> {
+    let automaton = Automaton::new(words);
+    let mut matches = vec![];
+
+    for &(dy, dx) in &TRANSLATIONS {
+        for y in 0..grid.ylen {
+            for x in 0..grid.xlen {
+                // Only walk lines from the cell where they enter the grid
+                if !grid.out_of_bounds(y - dy, x - dx) {
+                    continue
+                }
+
+                let mut state = 0;
+                let (mut ly, mut lx) = (y, x);
+
+                while !grid.out_of_bounds(ly, lx) {
+                    state = automaton.step(state, grid.get(ly, lx));
+
+                    for &i in &automaton.output[state] {
+                        let back = automaton.words[i].len() as isize - 1;
+
+                        matches.push(Match {
+                            word: words[i].clone(),
+                            start: (ly - dy * back, lx - dx * back),
+                            direction: (dy, dx)
+                        });
+                    }
+
+                    ly += dy;
+                    lx += dx;
+                }
+            }
+        }
+    }
+
+    matches.sort_by_key(|m| (m.start, m.direction));
+    matches
+}
 
-        if &word == "XMAS" {
-            count += 1;
+#[test]
+fn test_find_words() {
+    let grid = parse("1");
+    let words = vec!["XMAS".to_string(), "SAMX".to_string(), "MAS".to_string()];
+    let matches = find_words(&grid, &words);
+
+    assert_eq!(matches.iter().filter(|m| m.word == "XMAS").count(), 18);
+    assert_eq!(matches.iter().filter(|m| m.word == "SAMX").count(), 18);
+    assert!(matches.contains(&Match {
+        word: "XMAS".to_string(),
+        start: (0, 5),
+        direction: (0, 1)
+    }));
+    assert!(matches.contains(&Match {
+        word: "MAS".to_string(),
+        start: (0, 6),
+        direction: (0, 1)
+    }));
+}
+
+fn render(grid: &Grid, matches: &[Match]) -> String {
+    let mut vector = vec![vec!['.'; grid.xlen as usize]; grid.ylen as usize];
+
+    for m in matches {
+        for i in 0..m.word.chars().count() as isize {
+            let y = m.start.0 + m.direction.0 * i;
+            let x = m.start.1 + m.direction.1 * i;
+            vector[y as usize][x as usize] = grid.get(y, x);
         }
     }
 
-    count
+    vector
+        .iter()
+        .map(|row| row.iter().collect::<String>() + "\n")
+        .collect()
+}
+
+#[test]
+fn test_render() {
+    let grid = parse("1");
+    let matches = find_words(&grid, &["XMAS".to_string()]);
+    let rendered = render(&grid, &matches);
+
+    assert_eq!(rendered.lines().next(), Some("....XXMAS."));
+}
+
+fn xmas_count(grid: &Grid) -> usize {
+    find_words(grid, &["XMAS".to_string()]).len()
 }
 
 #[test]
@@ -106,39 +252,102 @@ fn test_xmas_counts() {
     assert_eq!(xmas_count(&grid), 18)
 }
 
-fn x_mas_count(grid: &Grid) -> usize {
-    let mut centres = vec![];
+fn parse_shape(shape: &str) -> RawGrid {
+    let rows: Vec<Vec<char>> = shape.lines().map(|l| l.chars().collect()).collect();
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+
+    rows
+        .into_iter()
+        .map(|mut r| {
+            r.resize(width, '.');
+            r
+        })
+        .collect()
+}
+
+fn rotate(shape: &RawGrid) -> RawGrid {
+    let ylen = shape.len();
+    let xlen = shape.first().map_or(0, |r| r.len());
+
+    (0..xlen)
+        .map(|x| (0..ylen).rev().map(|y| shape[y][x]).collect())
+        .collect()
+}
 
-    for y in 0..grid.ylen {
-        for x in 0..grid.xlen {
-            if grid.get(y, x) == 'A' {
-                centres.push((y, x));
+fn reflect(shape: &RawGrid) -> RawGrid {
+    shape
+        .iter()
+        .map(|r| r.iter().rev().copied().collect())
+        .collect()
+}
+
+// All distinct rotations and reflections of a shape
+fn orientations(shape: &RawGrid) -> Vec<RawGrid> {
+    let mut all: Vec<RawGrid> = vec![];
+    let mut current = shape.clone();
+
+    for _ in 0..4 {
+        for candidate in [current.clone(), reflect(&current)] {
+            if !all.contains(&candidate) {
+                all.push(candidate);
             }
         }
+        current = rotate(&current);
     }
 
-    centres
-        .iter()
-        .filter(|centre| is_a_valid_x(&grid, centre))
-        .count()
+    all
+}
+
+// Returns the top left position of every match, together with the index
+// of the orientation that matched.
+fn match_shape(grid: &Grid, shape: &str) -> Vec<(Point, usize)> {
+    let mut matches = vec![];
+
+    for (i, orientation) in orientations(&parse_shape(shape)).iter().enumerate() {
+        let ylen = orientation.len() as isize;
+        let xlen = orientation[0].len() as isize;
+
+        for y in 0..=(grid.ylen - ylen) {
+            for x in 0..=(grid.xlen - xlen) {
+                let fits = (0..ylen).all(|dy| {
+                    (0..xlen).all(|dx| {
+                        let c = orientation[dy as usize][dx as usize];
+                        c == '.' || c == grid.get(y + dy, x + dx)
+                    })
+                });
+
+                if fits {
+                    matches.push(((y, x), i));
+                }
+            }
+        }
+    }
+
+    matches.sort();
+    matches
 }
 
-fn is_a_valid_x(grid: &Grid, point: &Point) -> bool {
-    let mut word = String::new();
+#[test]
+fn test_match_shape() {
+    let grid = parse("1");
 
-    for (dy, dx) in &CORNERS {
-        let ddy = dy + point.0;
-        let ddx = dx + point.1;
+    assert_eq!(orientations(&parse_shape(X_MAS)).len(), 4);
+    assert_eq!(orientations(&parse_shape("A.\nBC")).len(), 8);
+    assert_eq!(orientations(&parse_shape(".A.\nAAA\n.A.")).len(), 1);
 
-        if grid.out_of_bounds(ddy, ddx) {
-            continue
-        }
+    let diagonals = find_words(&grid, &["XMAS".to_string()])
+        .iter()
+        .filter(|m| m.direction.0 != 0 && m.direction.1 != 0)
+        .count();
+    let bar = "X\n.M\n..A\n...S";
+    assert_eq!(match_shape(&grid, bar).len(), diagonals);
 
-        let c = grid.get(ddy, ddx);
-        word.push(c);
-    }
+    let plus = ".S.\nMAA\n.M.";
+    assert!(match_shape(&grid, plus).contains(&((1, 5), 0)));
+}
 
-    VALID_WORDS.contains(&word.as_str())
+fn x_mas_count(grid: &Grid) -> usize {
+    match_shape(grid, X_MAS).len()
 }
 
 #[test]
@@ -1,14 +1,63 @@
 use std::fs;
-use std::cmp::Ordering;
+use std::env;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 
 type OrderRules = (u32, u32);
 type Pages = Vec<u32>;
 
+#[derive(Debug, PartialEq)]
+enum Order {
+    Unique(Pages),
+    Ambiguous(Pages),
+    Cycle(Pages)
+}
+
+// All rules indexed by the page that has to come first
+struct RuleGraph {
+    after: HashMap<u32, Vec<u32>>
+}
+
+impl RuleGraph {
+    fn new(rules: &[OrderRules]) -> RuleGraph {
+        let mut after: HashMap<u32, Vec<u32>> = HashMap::new();
+
+        for &(l, r) in rules {
+            after.entry(l).or_default().push(r);
+        }
+
+        RuleGraph { after }
+    }
+
+    fn successors(&self, page: u32) -> &[u32] {
+        self.after.get(&page).map_or(&[], |s| s.as_slice())
+    }
+}
+
 fn main() {
-    let (ordered_rules, mut pages) = parse("input");
+    let (ordered_rules, pages) = parse("input");
+    let graph = RuleGraph::new(&ordered_rules);
+
+    println!("p1 {}", ordered_pages(&graph, &pages));
+    println!("p2 {}", unordered_pages(&graph, &pages));
 
-    println!("p1 {}", ordered_pages(&ordered_rules, &pages));
-    println!("p2 {}", unordered_pages(&ordered_rules, &mut pages));
+    if env::args().nth(1).is_some_and(|a| a == "explain") {
+        for (i, update) in pages.iter().enumerate() {
+            if !is_ordered(&graph, update) {
+                print!("{}", explain(&graph, update).render(i));
+            }
+        }
+    }
+
+    if env::args().nth(1).is_some_and(|a| a == "orders") {
+        for (i, update) in pages.iter().enumerate() {
+            match topological_order(&graph, update) {
+                Order::Ambiguous(_) => println!("update {} has no unique order", i),
+                Order::Cycle(cycle) => println!("update {} has a cycle in {:?}", i, cycle),
+                Order::Unique(_) => {}
+            }
+        }
+    }
 }
 
 fn parse(input: &'static str) -> (Vec<OrderRules>, Vec<Pages>) {
@@ -39,10 +88,10 @@ fn parse(input: &'static str) -> (Vec<OrderRules>, Vec<Pages>) {
     (order_rules, pages)
 }
 
-fn ordered_pages(rules: &Vec<OrderRules>, book: &Vec<Pages>) -> u32 {
+fn ordered_pages(graph: &RuleGraph, book: &[Pages]) -> u32 {
     book
         .iter()
-        .filter(|pages| is_ordered(rules, pages))
+        .filter(|pages| is_ordered(graph, pages))
         .map(|pages| pages[pages.len() / 2])
         .sum()
 }
@@ -50,46 +99,327 @@ fn ordered_pages(rules: &Vec<OrderRules>, book: &Vec<Pages>) -> u32 {
 #[test]
 fn test_count_ordered_pages() {
     let (ordered_rules, pages) = parse("1");
+    let graph = RuleGraph::new(&ordered_rules);
 
-    assert_eq!(ordered_pages(&ordered_rules, &pages), 143);
+    assert_eq!(ordered_pages(&graph, &pages), 143);
 }
 
-fn unordered_pages(rules: &Vec<OrderRules>, book: &mut Vec<Pages>) -> u32 {
+fn unordered_pages(graph: &RuleGraph, book: &[Pages]) -> u32 {
     book
-        .into_iter()
-        .filter(|pages| !is_ordered(rules, pages))
-        .map(|pages| {
-            pages.sort_by(|a, b| order(rules, *a, *b));
-            pages[pages.len() / 2]
+        .iter()
+        .filter(|pages| !is_ordered(graph, pages))
+        .filter_map(|pages| match topological_order(graph, pages) {
+            Order::Unique(order) | Order::Ambiguous(order) => Some(order[order.len() / 2]),
+            Order::Cycle(_) => None
         })
         .sum()
 }
 
-fn order(rules: &Vec<OrderRules>, a: u32, b: u32) -> Ordering {
-    let lr = rules.iter().find(|&&(l, r)| l == a && r == b);
+// Kahn's algorithm over the rules between the pages of this update only.
+// Whenever more than one page is ready the order isn't unique; ties are
+// broken by the original position of the page.
+fn topological_order(graph: &RuleGraph, pages: &Pages) -> Order {
+    let set: HashSet<u32> = pages.iter().copied().collect();
+    let mut incoming: HashMap<u32, usize> = pages.iter().map(|&p| (p, 0)).collect();
+
+    for &page in pages {
+        for s in graph.successors(page) {
+            if let Some(n) = incoming.get_mut(s) {
+                *n += 1;
+            }
+        }
+    }
+
+    let position: HashMap<u32, usize> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| (p, i))
+        .collect();
+    let mut ready: BinaryHeap<Reverse<(usize, u32)>> = pages
+        .iter()
+        .filter(|p| incoming[p] == 0)
+        .map(|&p| Reverse((position[&p], p)))
+        .collect();
+    let mut order = vec![];
+    let mut unique = true;
+
+    while let Some(Reverse((_, page))) = ready.pop() {
+        unique &= ready.is_empty();
+        order.push(page);
+
+        for s in graph.successors(page) {
+            if !set.contains(s) {
+                continue
+            }
+
+            let n = incoming.get_mut(s).unwrap();
+            *n -= 1;
+            if *n == 0 {
+                ready.push(Reverse((position[s], *s)));
+            }
+        }
+    }
+
+    if order.len() < pages.len() {
+        return Order::Cycle(cycle(graph, pages, &incoming))
+    }
 
-    if lr.is_some() {
-        Ordering::Less
+    if unique {
+        Order::Unique(order)
     } else {
-        Ordering::Greater
+        Order::Ambiguous(order)
     }
 }
 
-fn is_ordered(rules: &Vec<OrderRules>, pages: &Pages) -> bool {
-    rules.iter().all(|(left, right)| {
-        let lf = pages.iter().position(|&p| p == *left);
-        let rf = pages.iter().position(|&p| p == *right);
+// Every page Kahn's algorithm couldn't place has a predecessor it couldn't
+// place either, so walking back over those predecessors has to run into a
+// page twice. The pages in between are a cycle; pages that only come after a
+// cycle are never part of it. The cycle starts at its earliest page in the
+// update and follows the rules.
+fn cycle(graph: &RuleGraph, pages: &Pages, incoming: &HashMap<u32, usize>) -> Pages {
+    let stuck: Vec<u32> = pages.iter().copied().filter(|p| incoming[p] > 0).collect();
+    let mut before: HashMap<u32, u32> = HashMap::new();
+
+    for &page in stuck.iter() {
+        for s in graph.successors(page) {
+            if incoming.get(s).is_some_and(|&n| n > 0) {
+                before.entry(*s).or_insert(page);
+            }
+        }
+    }
 
-        match (lf, rf) {
-            (Some(l), Some(r)) => r > l,
-            _ => true
+    let mut walk = vec![stuck[0]];
+    let mut seen: HashSet<u32> = HashSet::from([stuck[0]]);
+    loop {
+        let page = before[walk.last().unwrap()];
+        if !seen.insert(page) {
+            let start = walk.iter().position(|&p| p == page).unwrap();
+            walk.drain(..start);
+            break
         }
+        walk.push(page);
+    }
+
+    walk.reverse();
+    let first = (0..walk.len())
+        .min_by_key(|&i| pages.iter().position(|&p| p == walk[i]))
+        .unwrap();
+    walk.rotate_left(first);
+    walk
+}
+
+#[test]
+fn test_topological_order() {
+    let (ordered_rules, pages) = parse("2");
+    let graph = RuleGraph::new(&ordered_rules);
+
+    assert_eq!(topological_order(&graph, &pages[0]), Order::Cycle(vec![1, 2, 3]));
+    assert_eq!(topological_order(&graph, &pages[1]), Order::Ambiguous(vec![4, 5, 6]));
+    assert_eq!(topological_order(&graph, &pages[2]), Order::Unique(vec![4, 5]));
+    assert_eq!(topological_order(&graph, &pages[3]), Order::Cycle(vec![1, 2, 3]));
+    assert_eq!(topological_order(&graph, &pages[4]), Order::Cycle(vec![2, 3, 1]));
+    assert_eq!(unordered_pages(&graph, &pages), 0);
+}
+
+fn is_ordered(graph: &RuleGraph, pages: &Pages) -> bool {
+    let positions: HashMap<u32, usize> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| (p, i))
+        .collect();
+
+    pages.iter().enumerate().all(|(i, &page)| {
+        graph
+            .successors(page)
+            .iter()
+            .all(|s| positions.get(s).is_none_or(|&j| j > i))
     })
 }
 
 #[test]
 fn test_count_unordered_pages() {
-    let (ordered_rules, mut pages) = parse("1");
+    let (ordered_rules, pages) = parse("1");
+    let graph = RuleGraph::new(&ordered_rules);
+
+    assert_eq!(unordered_pages(&graph, &pages), 123);
+}
+
+#[derive(Debug, PartialEq)]
+struct Violation {
+    rule: OrderRules,
+    positions: (usize, usize)
+}
+
+#[derive(Debug, PartialEq)]
+struct Move {
+    page: u32,
+    from: usize,
+    to: usize
+}
+
+struct Explanation {
+    violations: Vec<Violation>,
+    moves: Vec<Move>,
+    corrected: Option<Pages>
+}
+
+impl Explanation {
+    fn render(&self, index: usize) -> String {
+        let mut out = format!("update {}\n", index);
+
+        for v in self.violations.iter() {
+            out += &format!(
+                "  breaks {}|{} ({} is at {}, {} is at {})\n",
+                v.rule.0, v.rule.1, v.rule.0, v.positions.0, v.rule.1, v.positions.1
+            );
+        }
+
+        for m in self.moves.iter() {
+            out += &format!("  move {} from {} to {}\n", m.page, m.from, m.to);
+        }
+
+        match &self.corrected {
+            Some(order) => out += &format!("  corrected {:?}\n", order),
+            None => out += "  no valid order\n"
+        }
+
+        out
+    }
+}
+
+fn explain(graph: &RuleGraph, pages: &Pages) -> Explanation {
+    let positions: HashMap<u32, usize> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| (p, i))
+        .collect();
+
+    let mut violations = vec![];
+    for (i, &page) in pages.iter().enumerate() {
+        for s in graph.successors(page) {
+            if let Some(&j) = positions.get(s) {
+                if j < i {
+                    violations.push(Violation { rule: (page, *s), positions: (i, j) });
+                }
+            }
+        }
+    }
+
+    let corrected = match topological_order(graph, pages) {
+        Order::Unique(order) | Order::Ambiguous(order) => Some(order),
+        Order::Cycle(_) => None
+    };
+
+    let moves = match &corrected {
+        Some(order) => moves(pages, order),
+        None => vec![]
+    };
+
+    Explanation { violations, moves, corrected }
+}
+
+// The pages that are already in the right relative order (the longest
+// increasing run of target positions) stay put; everything else moves, in
+// order of where it ends up. Each move takes the page out at from and puts
+// it back in at to, counted in the update as the moves before it left it.
+fn moves(pages: &Pages, order: &Pages) -> Vec<Move> {
+    let target: HashMap<u32, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| (p, i))
+        .collect();
+    let targets: Vec<usize> = pages.iter().map(|p| target[p]).collect();
+
+    let mut tails: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; targets.len()];
+
+    for (i, &t) in targets.iter().enumerate() {
+        let k = tails.partition_point(|&j| targets[j] < t);
+        if k > 0 {
+            previous[i] = Some(tails[k - 1]);
+        }
+
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+
+    let mut keep = vec![false; pages.len()];
+    let mut i = tails.last().copied();
+    while let Some(j) = i {
+        keep[j] = true;
+        i = previous[j];
+    }
+
+    // Pages in place are always in the order they end up in, so a moved
+    // page goes right after the last one of them that ends up before it
+    let mut placed: HashSet<u32> = (0..pages.len()).filter(|&i| keep[i]).map(|i| pages[i]).collect();
+    let mut moving: Vec<usize> = (0..pages.len()).filter(|&i| !keep[i]).collect();
+    moving.sort_by_key(|&i| targets[i]);
+
+    let mut current = pages.clone();
+    let mut moves = vec![];
+
+    for i in moving {
+        let page = pages[i];
+        let from = current.iter().position(|&p| p == page).unwrap();
+        current.remove(from);
+
+        let to = current
+            .iter()
+            .rposition(|p| placed.contains(p) && target[p] < targets[i])
+            .map_or(0, |j| j + 1);
+        current.insert(to, page);
+        placed.insert(page);
+
+        moves.push(Move { page, from, to });
+    }
+
+    moves
+}
+
+#[test]
+fn test_explain() {
+    let (ordered_rules, pages) = parse("1");
+    let graph = RuleGraph::new(&ordered_rules);
+
+    let explanation = explain(&graph, &pages[3]);
+    assert_eq!(explanation.violations, vec![Violation { rule: (97, 75), positions: (1, 0) }]);
+    assert_eq!(explanation.moves.len(), 1);
+    assert_eq!(explanation.corrected, Some(vec![97, 75, 47, 61, 53]));
+
+    let explanation = explain(&graph, &pages[5]);
+    assert_eq!(explanation.violations.len(), 4);
+    assert!(explanation.violations.contains(&Violation { rule: (47, 29), positions: (4, 3) }));
+    assert_eq!(explanation.moves, vec![
+        Move { page: 29, from: 3, to: 4 },
+        Move { page: 13, from: 1, to: 4 }
+    ]);
+    assert_eq!(explanation.corrected, Some(vec![97, 75, 47, 29, 13]));
+
+    for update in pages.iter() {
+        let explanation = explain(&graph, update);
+        let mut current = update.clone();
+
+        for m in explanation.moves.iter() {
+            assert_eq!(current.remove(m.from), m.page);
+            current.insert(m.to, m.page);
+        }
+        assert_eq!(Some(current), explanation.corrected);
+    }
+
+    // Several orders are valid, the one closest to the update needs one move
+    let (ordered_rules, pages) = parse("3");
+    let graph = RuleGraph::new(&ordered_rules);
+    let explanation = explain(&graph, &pages[0]);
+    assert_eq!(explanation.corrected, Some(vec![2, 1, 3, 4]));
+    assert_eq!(explanation.moves, vec![Move { page: 1, from: 0, to: 1 }]);
 
-    assert_eq!(unordered_pages(&ordered_rules, &mut pages), 123);
+    let (ordered_rules, pages) = parse("2");
+    let graph = RuleGraph::new(&ordered_rules);
+    let explanation = explain(&graph, &pages[0]);
+    assert_eq!(explanation.corrected, None);
+    assert!(explanation.render(0).ends_with("  no valid order\n"));
 }
@@ -1,8 +1,19 @@
 use std::fs;
+use std::env;
 use std::collections::HashSet;
 
+const TRANSLATIONS: [Point; 4] = [
+    (-1, 0), // UP
+    (0, 1),  // RIGHT
+    (1, 0),  // DOWN
+    (0, -1), // LEFT
+];
+
+const ARROWS: [char; 4] = ['^', '>', 'v', '<'];
+
 type RawGrid = Vec<Vec<char>>;
 type Point = (isize, isize);
+type State = (Point, usize);
 
 #[derive(PartialEq)]
 enum Route {
@@ -10,6 +21,100 @@ enum Route {
     ClosedLoop,
 }
 
+// One bit per (position, direction) state of the guard
+struct Visited {
+    bits: Vec<u64>
+}
+
+impl Visited {
+    fn new(size: usize) -> Visited {
+        Visited { bits: vec![0; size.div_ceil(64)] }
+    }
+
+    fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    fn insert(&mut self, i: usize) -> bool {
+        let (word, bit) = (i / 64, 1 << (i % 64));
+        let new = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        new
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+}
+
+// For every (position, direction) state the cell where the guard stops in
+// front of the next obstacle, or None if the guard walks off the grid.
+struct Jumps {
+    stops: Vec<Option<Point>>
+}
+
+impl Jumps {
+    fn new(grid: &Grid) -> Jumps {
+        let mut stops = vec![None; grid.states()];
+
+        for (direction, &(dy, dx)) in TRANSLATIONS.iter().enumerate() {
+            // Visit cells so the next cell in this direction is always done first
+            let ys: Vec<isize> = if dy > 0 {
+                (0..grid.ylen).rev().collect()
+            } else {
+                (0..grid.ylen).collect()
+            };
+            let xs: Vec<isize> = if dx > 0 {
+                (0..grid.xlen).rev().collect()
+            } else {
+                (0..grid.xlen).collect()
+            };
+
+            for &y in ys.iter() {
+                for &x in xs.iter() {
+                    let next = (y + dy, x + dx);
+
+                    stops[grid.state(&(y, x), direction)] = if grid.out_of_bounds(&next) {
+                        None
+                    } else if grid.get(&next) == '#' {
+                        Some((y, x))
+                    } else {
+                        stops[grid.state(&next, direction)]
+                    };
+                }
+            }
+        }
+
+        Jumps { stops }
+    }
+
+    // Same as the precomputed stop, unless the extra obstacle is in the way
+    fn next(&self, grid: &Grid, p: &Point, direction: usize, obstacle: &Point) -> Option<Point> {
+        let stop = self.stops[grid.state(p, direction)];
+        let (dy, dx) = TRANSLATIONS[direction];
+        let (oy, ox) = (obstacle.0 - p.0, obstacle.1 - p.1);
+
+        let steps = if dy == 0 && oy == 0 && ox * dx > 0 {
+            ox * dx
+        } else if dx == 0 && ox == 0 && oy * dy > 0 {
+            oy * dy
+        } else {
+            return stop
+        };
+
+        let reachable = match stop {
+            Some((sy, sx)) => ((sy - p.0) * dy + (sx - p.1) * dx) + 1,
+            None => isize::MAX
+        };
+
+        if steps <= reachable {
+            Some((p.0 + dy * (steps - 1), p.1 + dx * (steps - 1)))
+        } else {
+            stop
+        }
+    }
+}
+
 struct Grid {
     vector: RawGrid,
     ylen: isize,
@@ -28,31 +133,105 @@ impl Grid {
         p.0 < 0 || p.1 < 0 || p.0 >= self.ylen || p.1 >= self.xlen
     }
 
-    fn id(&self, p: &Point, dim: u32) -> isize {
-        ((p.0 * self.ylen) + p.1).pow(dim)
+    fn states(&self) -> usize {
+        (self.ylen * self.xlen * 4) as usize
+    }
+
+    fn state(&self, p: &Point, direction: usize) -> usize {
+        ((p.0 * self.xlen + p.1) as usize * 4) + direction
     }
 
     fn get(&self, p: &Point) -> char {
         self.vector[p.0 as usize][p.1 as usize]
     }
 
-    fn guard(&self) -> Point {
+    fn guards(&self) -> Vec<State> {
+        let mut guards = vec![];
+
         for y in 0..self.ylen {
-            for x in 0..self.ylen {
-                if self.get(&(y, x)) == '^' {
-                    return (y, x)
+            for x in 0..self.xlen {
+                if let Some(d) = ARROWS.iter().position(|&a| a == self.get(&(y, x))) {
+                    guards.push(((y, x), d));
                 }
             }
         }
 
-        panic!("No guard found")
+        guards
+    }
+
+    fn guard(&self) -> State {
+        match self.guards()[..] {
+            [guard] => guard,
+            [] => panic!("No guard found"),
+            _ => panic!("More than one guard found")
+        }
     }
 }
 
 fn main() {
     let grid = parse("input");
-    println!("p1 {}", unique_steps(&grid));
-    println!("p2 {}", valid_obstacle_count(&grid));
+    let args: Vec<String> = env::args().skip(1).collect();
+    print!("{}", run(&grid, &args));
+}
+
+// The multi guard simulation runs on any grid, everything else needs
+// exactly one guard.
+fn run(grid: &Grid, args: &[String]) -> String {
+    let arg = |i: usize| args.get(i).map(|a| a.as_str());
+    let mut out = String::new();
+
+    if arg(0) == Some("guards") {
+        let policies: Vec<Turn> = arg(1)
+            .unwrap_or_default()
+            .chars()
+            .map(Turn::from_char)
+            .collect();
+        let collision = match arg(2) {
+            Some("block") => Collision::Block,
+            Some("halt") => Collision::Halt,
+            _ => Collision::Pass
+        };
+
+        for (i, (outcome, cells)) in simulate(grid, &policies, &collision).iter().enumerate() {
+            out += &format!("{} {:?} {}\n", i, outcome, cells);
+        }
+        return out
+    }
+
+    let guards = grid.guards().len();
+    if guards != 1 {
+        return format!("Expected exactly one guard, found {}\n", guards)
+    }
+
+    out += &format!("p1 {}\n", unique_steps(grid));
+    out += &format!("p2 {}\n", valid_obstacle_count(grid));
+
+    if arg(0) == Some("loops") {
+        let render_grid = arg(1) == Some("render");
+
+        for report in loop_reports(grid) {
+            if render_grid {
+                out += &format!("{:?}\n{}\n", report.obstacle, render(grid, &report));
+            } else {
+                out += &format!("{}\n", report.text());
+            }
+        }
+    }
+
+    out
+}
+
+#[test]
+fn test_run() {
+    let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+    let grid = parse("3");
+    assert_eq!(run(&grid, &args(&["guards"])), "0 Left(5) 5\n1 Left(5) 5\n");
+    assert_eq!(run(&grid, &args(&["guards", "", "halt"])), "0 Collided((1, 2)) 3\n1 Collided((1, 2)) 2\n");
+    assert_eq!(run(&grid, &args(&[])), "Expected exactly one guard, found 2\n");
+
+    let grid = parse("1");
+    assert_eq!(run(&grid, &args(&[])), "p1 41\np2 6\n");
 }
 
 fn parse(input: &'static str) -> Grid {
@@ -64,8 +243,12 @@ fn parse(input: &'static str) -> Grid {
 }
 
 fn unique_steps(grid: &Grid) -> usize {
-    let (route, _) = obstacle(grid, None);
-    route.len()
+    let mut visited = Visited::new(grid.states());
+    patrol(grid, None, &mut visited, None);
+
+    (0..(grid.ylen * grid.xlen) as usize)
+        .filter(|i| (0..4).any(|d| visited.contains(i * 4 + d)))
+        .count()
 }
 
 #[test]
@@ -75,47 +258,73 @@ fn test_unique_steps() {
 }
 
 fn valid_obstacle_count(grid: &Grid) -> usize {
-    (0..grid.ylen).map(|y| {
-        (0..grid.xlen)
-            .filter(|x| grid.get(&(y, *x)) == '.')
-            .filter(|x| {
-                let (_, t) = obstacle(&grid, Some((y, *x)));
-                t == Route::ClosedLoop
-            })
-            .count()
-    }).sum()
-}
-
-fn obstacle(grid: &Grid, obstacle: Option<Point>) -> (HashSet<isize>, Route) {
-    let mut guard_point = grid.guard();
-    let mut route = HashSet::new();
-    let mut direction = 0;
-    let mut weight = 0;
-    let obst = obstacle.unwrap_or((-1, -1));
+    loop_obstacles(grid).len()
+}
 
-    if obstacle.is_some() {
-        weight = 1;
-    }
+// Only cells on the original route can change the guard's route
+fn loop_obstacles(grid: &Grid) -> Vec<Point> {
+    let mut visited = Visited::new(grid.states());
+    patrol(grid, None, &mut visited, None);
 
-    loop {
-        let mut moved_point = guard_point;
+    let candidates: Vec<Point> = (0..grid.ylen)
+        .flat_map(|y| (0..grid.xlen).map(move |x| (y, x)))
+        .filter(|p| grid.get(p) == '.')
+        .filter(|p| (0..4).any(|d| visited.contains(grid.state(p, d))))
+        .collect();
+
+    let jumps = Jumps::new(grid);
 
-        match direction {
-            0 => moved_point.0 -= 1,
-            1 => moved_point.1 += 1,
-            2 => moved_point.0 += 1,
-            3 => moved_point.1 -= 1,
-            _ => panic!("Invalid direction")
+    candidates
+        .into_iter()
+        .filter(|p| {
+            visited.clear();
+            teleport(grid, &jumps, p, &mut visited) == Route::ClosedLoop
+        })
+        .collect()
+}
+
+// Same as patrol, but jumps from turn to turn. Only the states right after
+// a turn are marked in visited.
+fn teleport(grid: &Grid, jumps: &Jumps, obstacle: &Point, visited: &mut Visited) -> Route {
+    let (mut guard_point, mut direction) = grid.guard();
+
+    loop {
+        match jumps.next(grid, &guard_point, direction, obstacle) {
+            None => return Route::OutOfBounds,
+            Some(stop) => guard_point = stop
         }
 
-        let id = grid.id(&moved_point, (direction * weight) + 1);
+        direction = (direction + 1) % 4;
 
-        if weight > 0 && route.contains(&id) {
-            return (route, Route::ClosedLoop)
+        if !visited.insert(grid.state(&guard_point, direction)) {
+            return Route::ClosedLoop
         }
+    }
+}
+
+// Walks the guard until it leaves the grid or ends up in a state it has
+// been in before, marking every (position, direction) state in visited.
+// Every state is also pushed to states, if given, including the repeated one.
+fn patrol(
+    grid: &Grid,
+    obstacle: Option<Point>,
+    visited: &mut Visited,
+    mut states: Option<&mut Vec<State>>
+) -> Route {
+    let (mut guard_point, mut direction) = grid.guard();
+    let obst = obstacle.unwrap_or((-1, -1));
+
+    visited.insert(grid.state(&guard_point, direction));
+    if let Some(states) = states.as_mut() {
+        states.push((guard_point, direction));
+    }
+
+    loop {
+        let (dy, dx) = TRANSLATIONS[direction];
+        let moved_point = (guard_point.0 + dy, guard_point.1 + dx);
 
         if grid.out_of_bounds(&moved_point) {
-            return (route, Route::OutOfBounds)
+            return Route::OutOfBounds
         }
 
         if grid.get(&moved_point) == '#' || moved_point == obst {
@@ -123,7 +332,14 @@ fn obstacle(grid: &Grid, obstacle: Option<Point>) -> (HashSet<isize>, Route) {
             direction %= 4;
         } else {
             guard_point = moved_point;
-            route.insert(id);
+        }
+
+        if let Some(states) = states.as_mut() {
+            states.push((guard_point, direction));
+        }
+
+        if !visited.insert(grid.state(&guard_point, direction)) {
+            return Route::ClosedLoop
         }
     }
 }
@@ -133,3 +349,267 @@ fn test_valid_obstacle_count() {
     let grid = parse("1");
     assert_eq!(valid_obstacle_count(&grid), 6)
 }
+
+#[test]
+fn test_wide_grid() {
+    let grid = parse("2");
+    assert_eq!(grid.guard(), ((1, 3), 0));
+    assert_eq!(unique_steps(&grid), 2);
+    assert_eq!(valid_obstacle_count(&grid), 0)
+}
+
+#[test]
+fn test_teleport_matches_patrol() {
+    let grid = parse("1");
+    let jumps = Jumps::new(&grid);
+    let mut visited = Visited::new(grid.states());
+
+    for y in 0..grid.ylen {
+        for x in 0..grid.xlen {
+            if grid.get(&(y, x)) != '.' {
+                continue
+            }
+
+            visited.clear();
+            let walked = patrol(&grid, Some((y, x)), &mut visited, None);
+            visited.clear();
+            let jumped = teleport(&grid, &jumps, &(y, x), &mut visited);
+
+            assert!(walked == jumped);
+        }
+    }
+}
+
+struct LoopReport {
+    obstacle: Point,
+    lead_in: usize,
+    cycle: Vec<State>
+}
+
+impl LoopReport {
+    fn text(&self) -> String {
+        let cells: Vec<String> = self.cycle
+            .iter()
+            .map(|((y, x), d)| format!("{},{}{}", y, x, ARROWS[*d]))
+            .collect();
+
+        format!(
+            "{:?} lead-in {} cycle {}: {}",
+            self.obstacle, self.lead_in, self.cycle.len(), cells.join(" ")
+        )
+    }
+}
+
+// Every state of the guard in order, up to and including the first
+// state that repeats (if it walks in a loop).
+fn trace(grid: &Grid, obstacle: &Point) -> (Vec<State>, Route) {
+    let mut visited = Visited::new(grid.states());
+    let mut states = vec![];
+    let route = patrol(grid, Some(*obstacle), &mut visited, Some(&mut states));
+
+    (states, route)
+}
+
+fn loop_reports(grid: &Grid) -> Vec<LoopReport> {
+    loop_obstacles(grid)
+        .into_iter()
+        .map(|obstacle| {
+            let (mut states, _) = trace(grid, &obstacle);
+            let repeated = states.pop().unwrap();
+            let lead_in = states.iter().position(|s| *s == repeated).unwrap();
+            let cycle = states.split_off(lead_in);
+
+            LoopReport { obstacle, lead_in, cycle }
+        })
+        .collect()
+}
+
+#[test]
+fn test_loop_reports() {
+    let grid = parse("1");
+    let reports = loop_reports(&grid);
+    assert_eq!(reports.len(), 6);
+
+    let report = reports.iter().find(|r| r.obstacle == (6, 3)).unwrap();
+    assert_eq!(report.lead_in, 0);
+    assert_eq!(report.cycle.len(), 22);
+    assert!(report.text().starts_with("(6, 3) lead-in 0 cycle 22: 6,4^ 5,4^"));
+
+    let report = reports.iter().find(|r| r.obstacle == (7, 6)).unwrap();
+    assert_eq!(report.lead_in, 19);
+    assert_eq!(report.cycle[0], ((6, 6), 3));
+    assert_eq!(report.cycle.len(), 16);
+}
+
+// Draws the cycle over the grid like the puzzle does: | and - for vertical
+// and horizontal movement, + where both happen and O for the obstacle.
+fn render(grid: &Grid, report: &LoopReport) -> String {
+    let mut vector = grid.vector.clone();
+
+    for &((y, x), d) in report.cycle.iter() {
+        let c = &mut vector[y as usize][x as usize];
+        let line = if d % 2 == 0 { '|' } else { '-' };
+
+        *c = match *c {
+            '.' | '^' | '>' | 'v' | '<' => line,
+            '|' | '-' if *c != line => '+',
+            other => other
+        };
+    }
+
+    let (oy, ox) = report.obstacle;
+    vector[oy as usize][ox as usize] = 'O';
+
+    vector
+        .iter()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[test]
+fn test_render() {
+    let grid = parse("1");
+    let reports = loop_reports(&grid);
+    let report = reports.iter().find(|r| r.obstacle == (6, 3)).unwrap();
+    let rendered = render(&grid, report);
+    let lines: Vec<&str> = rendered.lines().collect();
+
+    assert_eq!(lines[0], "....#.....");
+    assert_eq!(lines[1], "....+---+#");
+    assert_eq!(lines[6], ".#.O+---+.");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Turn {
+    Right,
+    Left,
+    Reverse
+}
+
+impl Turn {
+    fn from_char(c: char) -> Turn {
+        match c {
+            'R' => Turn::Right,
+            'L' => Turn::Left,
+            'B' => Turn::Reverse,
+            _ => panic!("Invalid turn {}", c)
+        }
+    }
+
+    fn apply(&self, direction: usize) -> usize {
+        match self {
+            Turn::Right => (direction + 1) % 4,
+            Turn::Left => (direction + 3) % 4,
+            Turn::Reverse => (direction + 2) % 4
+        }
+    }
+}
+
+// What happens when a guard walks into a cell taken by another guard
+#[derive(Debug, PartialEq)]
+enum Collision {
+    Pass,
+    Block,
+    Halt
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    Left(usize),
+    Looped,
+    Collided(Point)
+}
+
+// Moves all guards one step per tick, in reading order. Guards without a
+// policy turn right. Returns the outcome for each guard together with the
+// number of distinct cells it visited.
+fn simulate(grid: &Grid, policies: &[Turn], collision: &Collision) -> Vec<(Outcome, usize)> {
+    let mut guards = grid.guards();
+    let n = guards.len();
+    let mut outcomes: Vec<Option<Outcome>> = vec![None; n];
+    let mut cells: Vec<HashSet<Point>> = guards.iter().map(|(p, _)| HashSet::from([*p])).collect();
+    let mut seen = HashSet::new();
+    let mut tick = 0;
+
+    while outcomes.iter().any(|o| o.is_none()) {
+        let active: Vec<bool> = outcomes.iter().map(|o| o.is_none()).collect();
+        if !seen.insert((guards.clone(), active)) {
+            for o in outcomes.iter_mut().filter(|o| o.is_none()) {
+                *o = Some(Outcome::Looped);
+            }
+            break
+        }
+
+        tick += 1;
+
+        for i in 0..n {
+            if outcomes[i].is_some() {
+                continue
+            }
+
+            let (p, d) = guards[i];
+            let (dy, dx) = TRANSLATIONS[d];
+            let next = (p.0 + dy, p.1 + dx);
+            let turn = policies.get(i).copied().unwrap_or(Turn::Right);
+
+            if grid.out_of_bounds(&next) {
+                outcomes[i] = Some(Outcome::Left(tick));
+                continue
+            }
+
+            let other = (0..n).find(|&j| {
+                j != i && guards[j].0 == next && !matches!(outcomes[j], Some(Outcome::Left(_)))
+            });
+
+            let blocked = grid.get(&next) == '#' || match (other, collision) {
+                (Some(j), Collision::Halt) => {
+                    outcomes[i] = Some(Outcome::Collided(next));
+                    if outcomes[j].is_none() {
+                        outcomes[j] = Some(Outcome::Collided(next));
+                    }
+                    continue
+                },
+                (Some(_), Collision::Block) => true,
+                _ => false
+            };
+
+            if blocked {
+                guards[i].1 = turn.apply(d);
+            } else {
+                guards[i].0 = next;
+                cells[i].insert(next);
+            }
+        }
+    }
+
+    outcomes
+        .into_iter()
+        .zip(cells.iter())
+        .map(|(o, c)| (o.unwrap(), c.len()))
+        .collect()
+}
+
+#[test]
+fn test_simulate() {
+    let grid = parse("1");
+    assert_eq!(simulate(&grid, &[], &Collision::Pass), vec![(Outcome::Left(55), 41)]);
+
+    let grid = parse("3");
+    assert_eq!(
+        simulate(&grid, &[], &Collision::Pass),
+        vec![(Outcome::Left(5), 5), (Outcome::Left(5), 5)]
+    );
+    assert_eq!(
+        simulate(&grid, &[], &Collision::Block),
+        vec![(Outcome::Left(5), 4), (Outcome::Left(4), 3)]
+    );
+    assert_eq!(
+        simulate(&grid, &[], &Collision::Halt),
+        vec![(Outcome::Collided((1, 2)), 3), (Outcome::Collided((1, 2)), 2)]
+    );
+
+    let grid = parse("4");
+    assert_eq!(simulate(&grid, &[Turn::Right], &Collision::Pass), vec![(Outcome::Left(3), 2)]);
+    assert_eq!(simulate(&grid, &[Turn::Reverse], &Collision::Pass), vec![(Outcome::Looped, 3)]);
+}
@@ -1,72 +1,291 @@
 use std::fs;
-use std::collections::HashMap;
+use std::env;
+use digits::digit_length;
 
-type TestValues = HashMap<u64, Vec<u64>>;
+// Build with --features wide for totals that don't fit in 64 bits
+#[cfg(not(feature = "wide"))]
+type Value = u64;
+#[cfg(feature = "wide")]
+type Value = u128;
+type Sum = u128;
 
-fn main() {
-    let test_values = parse("input");
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Add,
+    Multiply,
+    Concatenate,
+    Subtract,
+    Divide,
+    Power
+}
+
+const PLAIN: [Operator; 2] = [Operator::Add, Operator::Multiply];
+const CONCATENATING: [Operator; 3] = [Operator::Add, Operator::Multiply, Operator::Concatenate];
+
+impl Operator {
+    fn from_char(c: char) -> Operator {
+        match c {
+            '+' => Operator::Add,
+            '*' => Operator::Multiply,
+            '|' => Operator::Concatenate,
+            '-' => Operator::Subtract,
+            '/' => Operator::Divide,
+            '^' => Operator::Power,
+            _ => panic!("Invalid operator {}", c)
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Multiply => "*",
+            Operator::Concatenate => "||",
+            Operator::Subtract => "-",
+            Operator::Divide => "/",
+            Operator::Power => "^"
+        }
+    }
+
+    fn apply(&self, k: Value, n: Value) -> Option<Value> {
+        match self {
+            Operator::Add => k.checked_add(n),
+            Operator::Multiply => k.checked_mul(n),
+            Operator::Concatenate => concat(k, n),
+            Operator::Subtract => k.checked_sub(n),
+            Operator::Divide => k.checked_div(n),
+            Operator::Power => k.checked_pow(exponent(n))
+        }
+    }
+
+    // Every operator is non-decreasing in its left operand, so all values k
+    // for which "k op n" lands in [lo, hi] form a range as well.
+    fn preimage(&self, lo: Value, hi: Value, n: Value) -> Option<(Value, Value)> {
+        let (l, h) = match self {
+            Operator::Add => (lo.saturating_sub(n), hi.checked_sub(n)?),
+            Operator::Multiply if n == 0 => {
+                if lo > 0 {
+                    return None
+                }
+                (0, Value::MAX)
+            },
+            Operator::Multiply => (lo.div_ceil(n), hi / n),
+            // Like concat, nothing can be put in front of n once its
+            // magnitude overflows, not even 0
+            Operator::Concatenate => {
+                let k = magnitude(n)?;
+                (lo.saturating_sub(n).div_ceil(k), hi.checked_sub(n)? / k)
+            },
+            Operator::Subtract => (lo.checked_add(n)?, hi.saturating_add(n)),
+            Operator::Divide if n == 0 => return None,
+            Operator::Divide => (lo.checked_mul(n)?, hi.saturating_mul(n).saturating_add(n - 1)),
+            Operator::Power if n == 0 => {
+                if lo > 1 || hi < 1 {
+                    return None
+                }
+                (0, Value::MAX)
+            },
+            Operator::Power => (root_ceil(lo, exponent(n)), root_floor(hi, exponent(n)))
+        };
+
+        if l > h {
+            return None
+        }
+
+        Some((l, h))
+    }
+}
 
-    println!("p1 {}", correct_test_values(&test_values, false));
-    println!("p2 {}", correct_test_values(&test_values, true));
+fn exponent(n: Value) -> u32 {
+    n.min(u32::MAX as Value) as u32
 }
 
-fn parse(input: &'static str) -> TestValues {
-    let mut map = HashMap::new();
+fn root_floor(v: Value, n: u32) -> Value {
+    let (mut lo, mut hi) = (0, v);
+
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if mid.checked_pow(n).is_some_and(|p| p <= v) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    lo
+}
+
+fn root_ceil(v: Value, n: u32) -> Value {
+    let r = root_floor(v, n);
+    if r.pow(n) == v { r } else { r + 1 }
+}
+
+struct Equation {
+    line: usize,
+    total: Value,
+    nums: Vec<Value>
+}
+
+impl Equation {
+    fn expression(&self, operators: &[Operator]) -> String {
+        let mut expression = self.nums[0].to_string();
+
+        for (op, n) in operators.iter().zip(self.nums[1..].iter()) {
+            expression += &format!(" {} {}", op.symbol(), n);
+        }
+
+        expression
+    }
+
+    fn evaluate(&self, operators: &[Operator]) -> Option<Value> {
+        operators
+            .iter()
+            .zip(self.nums[1..].iter())
+            .try_fold(self.nums[0], |k, (op, n)| op.apply(k, *n))
+    }
+}
+
+fn main() {
+    let equations = parse("input");
+
+    println!("p1 {}", correct_test_values(&equations, &PLAIN));
+    println!("p2 {}", correct_test_values(&equations, &CONCATENATING));
+
+    if env::args().nth(1).is_some_and(|a| a == "explain") {
+        let set: Vec<Operator> = match env::args().nth(2) {
+            Some(symbols) => symbols.chars().map(Operator::from_char).collect(),
+            None => CONCATENATING.to_vec()
+        };
+
+        for eq in equations.iter() {
+            if let Some(operators) = solve(eq, &set) {
+                let total = eq.evaluate(&operators).unwrap();
+                println!("{}: {} = {}", eq.line, eq.expression(&operators), total);
+            }
+        }
+    }
+}
 
-    for line in fs::read_to_string(input).unwrap().lines() {
+fn parse(input: &'static str) -> Vec<Equation> {
+    fs::read_to_string(input).unwrap().lines().enumerate().map(|(i, line)| {
         let (total_s, nums_s) = line.split_once(": ").unwrap();
-        let total = total_s.parse::<u64>().unwrap();
+        let total = total_s.parse::<Value>().unwrap();
         let nums = nums_s
             .split(" ")
-            .map(|i| i.parse::<u64>().unwrap())
+            .map(|i| i.parse::<Value>().unwrap())
             .collect();
 
-        map.insert(total, nums);
-    }
-
-    map
+        Equation { line: i + 1, total, nums }
+    }).collect()
 }
 
-fn correct_test_values(test_values: &TestValues, conc: bool) -> u64 {
-    test_values
+fn correct_test_values(equations: &[Equation], set: &[Operator]) -> Sum {
+    equations
         .iter()
-        .filter(|&(total, nums)| is_corr(nums[0], 1, *total, nums, conc))
-        .map(|(total, _)| total)
+        .filter(|eq| solve(eq, set).is_some())
+        .map(|eq| eq.total as Sum)
         .sum()
 }
 
-fn is_corr(k: u64, i: usize, l: u64, n: &Vec<u64>, conc: bool) -> bool {
-    if i == n.len() {
-        return k == l
+fn solve(eq: &Equation, set: &[Operator]) -> Option<Vec<Operator>> {
+    is_corr(eq.total, eq.total, eq.nums.len() - 1, &eq.nums, set)
+}
+
+// Works backwards from the total: the operators before n[i] have to end up
+// in [lo, hi]. Inverting an operator often leaves nothing (a total that
+// isn't divisible, doesn't end in the digits of n[i], ...), which prunes
+// most of the search. The range never grows past what can still reach the
+// total, so anything that would overflow on the way is pruned as well.
+fn is_corr(lo: Value, hi: Value, i: usize, n: &[Value], set: &[Operator]) -> Option<Vec<Operator>> {
+    if i == 0 {
+        return if lo <= n[0] && n[0] <= hi { Some(vec![]) } else { None }
     }
 
-    is_corr(k + n[i], i + 1, l, n, conc) ||
-    is_corr(k * n[i], i + 1, l, n, conc) ||
-    (conc && is_corr(concat(k, n[i]), i + 1, l, n, conc))
+    set.iter().find_map(|op| {
+        let (l, h) = op.preimage(lo, hi, n[i])?;
+        let mut operators = is_corr(l, h, i - 1, n, set)?;
+        operators.push(*op);
+        Some(operators)
+    })
 }
 
 #[test]
 fn test_correct_test_values() {
-    let test_values = parse("1");
+    let equations = parse("1");
 
-    assert_eq!(correct_test_values(&test_values, false), 3749)
+    assert_eq!(correct_test_values(&equations, &PLAIN), 3749)
 }
 
-fn concat(n: u64, m: u64) -> u64 {
-    let m_len = ((m as f64).log10().floor() + 1.0) as u32;
-    let k = 10_u64.pow(m_len);
+#[test]
+fn test_duplicate_test_values() {
+    let equations = parse("2");
 
-    (n * k) + m
+    assert_eq!(equations.len(), 3);
+    assert_eq!(equations[1].line, 2);
+    assert_eq!(correct_test_values(&equations, &PLAIN), 20)
+}
+
+#[test]
+fn test_solve() {
+    let equations = parse("1");
+
+    let operators = solve(&equations[1], &PLAIN).unwrap();
+    assert_eq!(equations[1].expression(&operators), "81 * 40 + 27");
+
+    let operators = solve(&equations[4], &CONCATENATING).unwrap();
+    assert_eq!(equations[4].expression(&operators), "6 * 8 || 6 * 15");
+
+    assert_eq!(solve(&equations[2], &CONCATENATING), None);
+}
+
+fn magnitude(m: Value) -> Option<Value> {
+    (10 as Value).checked_pow(digit_length(m))
+}
+
+fn concat(n: Value, m: Value) -> Option<Value> {
+    n.checked_mul(magnitude(m)?)?.checked_add(m)
 }
 
 #[test]
 fn test_concat() {
-    assert_eq!(concat(123, 45), 12345);
+    assert_eq!(concat(123, 45), Some(12345));
+    assert_eq!(concat(12, 0), Some(120));
+    assert_eq!(concat(1, Value::MAX), None);
+    assert_eq!(concat(0, Value::MAX), None);
 }
 
 #[test]
 fn test_concat_correct() {
-    let test_values = parse("1");
+    let equations = parse("1");
+
+    assert_eq!(correct_test_values(&equations, &CONCATENATING), 11387)
+}
+
+#[test]
+fn test_operator_sets() {
+    let equations = parse("3");
+    let all: Vec<Operator> = "+*|-/^".chars().map(Operator::from_char).collect();
+
+    for eq in equations.iter() {
+        let operators = solve(eq, &all).unwrap();
+        assert_eq!(eq.evaluate(&operators), Some(eq.total), "{}", eq.expression(&operators));
+    }
+
+    assert_eq!(equations[0].expression(&solve(&equations[0], &all).unwrap()), "9 - 3 ^ 2");
+    assert_eq!(equations[1].expression(&solve(&equations[1], &all).unwrap()), "100 / 7 + 1");
+    assert_eq!(solve(&equations[0], &PLAIN), None);
+}
+
+#[test]
+fn test_hostile_values() {
+    let equations = parse("4");
+    let all: Vec<Operator> = "+*|-/^".chars().map(Operator::from_char).collect();
+
+    assert_eq!(correct_test_values(&equations, &PLAIN), 18446744073709551615);
+    assert_eq!(equations[1].expression(&solve(&equations[1], &CONCATENATING).unwrap()), "1 || 0");
+    assert_eq!(solve(&equations[2], &CONCATENATING), None);
+    assert_eq!(equations[2].expression(&solve(&equations[2], &all).unwrap()), "18446744073709551615 - 18446744073709551615 + 5");
 
-    assert_eq!(correct_test_values(&test_values, true), 11387)
+    let eq = Equation { line: 5, total: Value::MAX, nums: vec![0, Value::MAX] };
+    assert_eq!(solve(&eq, &[Operator::Concatenate]), None);
+    assert_eq!(correct_test_values(&[eq], &[Operator::Concatenate]), 0);
 }
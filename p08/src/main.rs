@@ -1,9 +1,42 @@
 use std::fs;
-use std::collections::{HashSet, HashMap};
+use std::env;
+use std::collections::{BTreeMap, HashSet, HashMap};
 
 type RawGrid = Vec<Vec<char>>;
 type Point = (isize, isize);
 type Antennas = HashMap<char, Vec<Point>>;
+type Antinodes = BTreeMap<char, HashSet<Point>>;
+
+enum Rule {
+    // Points k times the distance between two antennas beyond either one
+    Multiples(Vec<isize>),
+    // Every lattice point on the line through both antennas
+    Line,
+    // Points between both antennas, splitting the distance p:q and q:p
+    Ratio(isize, isize)
+}
+
+impl Rule {
+    // "line", "multiples:1,2" or "ratio:1:2"
+    fn parse(rule: &str) -> Rule {
+        let (name, args) = rule.split_once(":").unwrap_or((rule, ""));
+        let numbers: Vec<isize> = args
+            .split([',', ':'])
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse::<isize>().unwrap())
+            .collect();
+
+        match name {
+            "line" => Rule::Line,
+            "multiples" => Rule::Multiples(numbers),
+            "ratio" => match numbers[..] {
+                [p, q] if p > 0 && q > 0 => Rule::Ratio(p, q),
+                _ => panic!("Invalid rule {}", rule)
+            },
+            _ => panic!("Invalid rule {}", rule)
+        }
+    }
+}
 
 struct Grid {
     vector: RawGrid,
@@ -33,6 +66,29 @@ fn main() {
     let antennas = get_antennas(&grid);
     println!("p1 {}", uniq_antinodes(&grid, &antennas));
     println!("p2 {}", uniq_resonating_antinodes(&grid, &antennas));
+
+    let Some(mode) = env::args().nth(1) else { return };
+    let mut rules: Vec<Rule> = env::args().skip(2).map(|r| Rule::parse(&r)).collect();
+    if rules.is_empty() {
+        rules.push(Rule::Line);
+    }
+    let map = antinodes(&grid, &antennas, &rules);
+
+    match mode.as_str() {
+        "breakdown" => {
+            for (frequency, points) in map.iter() {
+                println!("{} {}", frequency, points.len());
+            }
+        },
+        "render" => {
+            println!("{}", render(&grid, &map, None));
+            for &frequency in map.keys() {
+                println!("\n{}\n{}", frequency, render(&grid, &map, Some(frequency)));
+            }
+        },
+        "export" => print!("{}", export(&map)),
+        _ => panic!("Invalid mode {}", mode)
+    }
 }
 
 fn parse(input: &'static str) -> Grid {
@@ -43,31 +99,75 @@ fn parse(input: &'static str) -> Grid {
     Grid::new(vector)
 }
 
-fn uniq_antinodes(grid: &Grid, antennas: &Antennas) -> usize {
-    let mut set = HashSet::new();
+fn gcd(a: isize, b: isize) -> isize {
+    if b == 0 { a.abs() } else { gcd(b, a % b) }
+}
+
+fn antinodes(grid: &Grid, antennas: &Antennas, rules: &[Rule]) -> Antinodes {
+    let mut map: Antinodes = BTreeMap::new();
+
+    for (&frequency, ants) in antennas {
+        let set = map.entry(frequency).or_default();
 
-    for (_, ants) in antennas {
         for i in 0..ants.len() {
             for j in (i + 1)..ants.len() {
                 let (ky, kx) = ants[i];
                 let (ly, lx) = ants[j];
                 let (dy, dx) = (ky - ly, kx - lx);
 
-                let a = (ky + dy, kx + dx);
-                let b = (ly - dy, lx - dx);
+                for rule in rules {
+                    let points: Vec<Point> = match rule {
+                        Rule::Multiples(ks) => ks
+                            .iter()
+                            .flat_map(|k| [(ky + dy * k, kx + dx * k), (ly - dy * k, lx - dx * k)])
+                            .collect(),
+                        Rule::Line => {
+                            let g = gcd(dy, dx);
+                            let (sy, sx) = (dy / g, dx / g);
+                            let mut points = vec![];
+                            let (mut y, mut x) = (ly, lx);
 
-                if !grid.out_of_bounds(&a) {
-                    set.insert(a);
-                }
+                            while !grid.out_of_bounds(&(y, x)) {
+                                points.push((y, x));
+                                y += sy;
+                                x += sx;
+                            }
+
+                            let (mut y, mut x) = (ly - sy, lx - sx);
+                            while !grid.out_of_bounds(&(y, x)) {
+                                points.push((y, x));
+                                y -= sy;
+                                x -= sx;
+                            }
+
+                            points
+                        },
+                        Rule::Ratio(p, q) => [(*p, *q), (*q, *p)]
+                            .iter()
+                            .filter(|(a, b)| (dy * a) % (a + b) == 0 && (dx * a) % (a + b) == 0)
+                            .map(|(a, b)| (ly + dy * a / (a + b), lx + dx * a / (a + b)))
+                            .collect()
+                    };
 
-                if !grid.out_of_bounds(&b) {
-                    set.insert(b);
+                    set.extend(points.into_iter().filter(|p| !grid.out_of_bounds(p)));
                 }
             }
         }
     }
 
-    set.len()
+    map
+}
+
+fn uniq(antinodes: &Antinodes) -> usize {
+    antinodes
+        .values()
+        .flatten()
+        .collect::<HashSet<&Point>>()
+        .len()
+}
+
+fn uniq_antinodes(grid: &Grid, antennas: &Antennas) -> usize {
+    uniq(&antinodes(grid, antennas, &[Rule::Multiples(vec![1])]))
 }
 
 #[test]
@@ -79,31 +179,7 @@ fn test_uniq_antinodes() {
 }
 
 fn uniq_resonating_antinodes(grid: &Grid, antennas: &Antennas) -> usize {
-    let mut set = HashSet::new();
-
-    for (_, ants) in antennas {
-        for i in 0..ants.len() {
-            for j in (i + 1)..ants.len() {
-                let (mut ay, mut ax) = ants[i];
-                let (mut by, mut bx) = ants[j];
-                let (dy, dx) = (ay - by, ax - bx);
-
-                while !grid.out_of_bounds(&(ay, ax)) {
-                    set.insert((ay, ax));
-                    ay += dy;
-                    ax += dx;
-                }
-
-                while !grid.out_of_bounds(&(by, bx)) {
-                    set.insert((by, bx));
-                    by -= dy;
-                    bx -= dx;
-                }
-            }
-        }
-    }
-
-    set.len()
+    uniq(&antinodes(grid, antennas, &[Rule::Line]))
 }
 
 #[test]
@@ -122,6 +198,104 @@ fn test_uniq_resonating_antinodes_2() {
     assert_eq!(uniq_resonating_antinodes(&grid, &antennas), 9);
 }
 
+#[test]
+fn test_antinode_rules() {
+    let grid = parse("3");
+    let antennas = get_antennas(&grid);
+
+    let line = antinodes(&grid, &antennas, &[Rule::Line]);
+    assert_eq!(line[&'a'].len(), 4);
+    assert!(line[&'a'].contains(&(1, 2)));
+    assert_eq!(line[&'b'].len(), 4);
+    assert_eq!(uniq(&line), 8);
+
+    let ratio = antinodes(&grid, &antennas, &[Rule::parse("ratio:1:2")]);
+    assert!(ratio[&'a'].is_empty());
+    assert_eq!(ratio[&'b'], HashSet::from([(2, 2), (3, 4)]));
+
+    let grid = parse("1");
+    let antennas = get_antennas(&grid);
+    let both = antinodes(&grid, &antennas, &[Rule::Multiples(vec![1]), Rule::Ratio(1, 2)]);
+    assert_eq!(uniq(&both), 14);
+    assert_eq!(antinodes(&grid, &antennas, &[Rule::Multiples(vec![1, 2])])[&'A'].len(), 7);
+}
+
+// Antennas keep their frequency, '#' is an antinode, '*' an antinode on top
+// of an antenna and '+' an antinode shared by several frequencies. Given a
+// frequency only its antennas and antinodes are drawn.
+fn render(grid: &Grid, antinodes: &Antinodes, frequency: Option<char>) -> String {
+    let mut out = String::new();
+
+    for y in 0..grid.ylen {
+        for x in 0..grid.xlen {
+            let c = grid.get(&(y, x));
+            let antenna = c != '.' && frequency.is_none_or(|f| f == c);
+            let count = antinodes
+                .iter()
+                .filter(|(f, points)| frequency.is_none_or(|g| g == **f) && points.contains(&(y, x)))
+                .count();
+
+            out.push(match (antenna, count) {
+                (true, 0) => c,
+                (true, _) => '*',
+                (false, 0) => '.',
+                (false, 1) => '#',
+                (false, _) => '+'
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+#[test]
+fn test_render() {
+    let grid = parse("1");
+    let antennas = get_antennas(&grid);
+    let map = antinodes(&grid, &antennas, &[Rule::Multiples(vec![1])]);
+    let rendered = render(&grid, &map, None);
+    let lines: Vec<&str> = rendered.lines().collect();
+
+    assert_eq!(lines[0], "......#....#");
+    assert_eq!(lines[1], "...+....0...");
+    assert_eq!(lines[5], ".#....*.....");
+    assert_eq!(lines[8], "........A...");
+
+    let rendered = render(&grid, &map, Some('A'));
+    let lines: Vec<&str> = rendered.lines().collect();
+
+    assert_eq!(lines[0], "............");
+    assert_eq!(lines[1], "...#........");
+    assert_eq!(lines[5], "......A.....");
+}
+
+fn export(antinodes: &Antinodes) -> String {
+    antinodes
+        .iter()
+        .map(|(frequency, points)| {
+            let mut points: Vec<&Point> = points.iter().collect();
+            points.sort();
+
+            let list: Vec<String> = points
+                .iter()
+                .map(|(y, x)| format!("{},{}", y, x))
+                .collect();
+
+            format!("{}: {}\n", frequency, list.join(" "))
+        })
+        .collect()
+}
+
+#[test]
+fn test_export() {
+    let grid = parse("3");
+    let antennas = get_antennas(&grid);
+    let map = antinodes(&grid, &antennas, &[Rule::Line]);
+
+    assert_eq!(export(&map), "a: 0,0 1,2 2,4 3,6\nb: 1,0 2,2 3,4 4,6\n");
+}
+
 fn get_antennas(grid: &Grid) -> Antennas {
     let mut antennas: Antennas = HashMap::new();
 
@@ -141,3 +315,15 @@ fn get_antennas(grid: &Grid) -> Antennas {
 
     antennas
 }
+
+#[test]
+#[should_panic(expected = "Invalid rule")]
+fn test_invalid_ratio() {
+    Rule::parse("ratio:1");
+}
+
+#[test]
+#[should_panic(expected = "Invalid rule")]
+fn test_zero_ratio() {
+    Rule::parse("ratio:0:0");
+}
@@ -1,14 +1,526 @@
 use std::fs;
-use std::cmp;
+use std::env;
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, BTreeMap, BTreeSet};
 
 type Layout = Vec<usize>;
 type DiskMap = (Layout, Layout);
 
+#[derive(Debug, PartialEq)]
+struct Fragmentation {
+    free_blocks: usize,
+    free_spans: usize,
+    largest_free_span: usize,
+    fragmented_files: usize
+}
+
+// A segment tree over block positions, folding the values of all positions
+// with either max or min
+struct Tree {
+    nodes: Vec<usize>,
+    width: usize,
+    fold: fn(usize, usize) -> usize,
+    empty: usize
+}
+
+impl Tree {
+    fn new(length: usize, fold: fn(usize, usize) -> usize, empty: usize) -> Tree {
+        let width = length.next_power_of_two();
+        Tree { nodes: vec![empty; 2 * width], width, fold, empty }
+    }
+
+    fn set(&mut self, i: usize, value: usize) {
+        let mut node = i + self.width;
+        self.nodes[node] = value;
+
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = (self.fold)(self.nodes[2 * node], self.nodes[2 * node + 1]);
+        }
+    }
+
+    // The fold of every position before limit
+    fn before(&self, limit: usize) -> usize {
+        let (mut lo, mut hi) = (self.width, limit.min(self.width) + self.width);
+        let mut result = self.empty;
+
+        while lo < hi {
+            if lo % 2 == 1 {
+                result = (self.fold)(result, self.nodes[lo]);
+                lo += 1;
+            }
+            if hi % 2 == 1 {
+                hi -= 1;
+                result = (self.fold)(result, self.nodes[hi]);
+            }
+            lo /= 2;
+            hi /= 2;
+        }
+
+        result
+    }
+
+    // The leftmost position before limit with a value that holds for found.
+    // found has to hold for the fold of a node whenever it holds for one of
+    // the values in it.
+    fn find(&self, limit: usize, found: &dyn Fn(usize) -> bool) -> Option<usize> {
+        self.descend(1, 0, self.width, limit, found)
+    }
+
+    fn descend(
+        &self,
+        node: usize,
+        lo: usize,
+        hi: usize,
+        limit: usize,
+        found: &dyn Fn(usize) -> bool
+    ) -> Option<usize> {
+        if lo >= limit || !found(self.nodes[node]) {
+            return None
+        }
+
+        if hi - lo == 1 {
+            return Some(lo)
+        }
+
+        let mid = (lo + hi) / 2;
+        self.descend(2 * node, lo, mid, limit, found)
+            .or_else(|| self.descend(2 * node + 1, mid, hi, limit, found))
+    }
+}
+
+// Files are at most 9 blocks long, so spans shorter than that are also kept
+// by their exact size, and only the longer ones need a tree for best fit.
+const LONGEST_FILE: usize = 9;
+
+// The free spans of a disk as start -> length, indexed so the span a file
+// fits in can be found in O(log n) for every kind of fit
+struct FreeSpans {
+    spans: BTreeMap<usize, usize>,
+    largest: Tree,
+    smallest_long: Tree,
+    short: Vec<BTreeSet<usize>>
+}
+
+impl FreeSpans {
+    fn new(blocks: &[Option<usize>]) -> FreeSpans {
+        let length = blocks.len() + 1;
+        let mut free = FreeSpans {
+            spans: BTreeMap::new(),
+            largest: Tree::new(length, cmp::max, 0),
+            smallest_long: Tree::new(length, cmp::min, usize::MAX),
+            short: vec![BTreeSet::new(); LONGEST_FILE]
+        };
+
+        for (i, owner) in blocks.iter().enumerate() {
+            if owner.is_none() {
+                free.release(i);
+            }
+        }
+
+        free
+    }
+
+    fn insert(&mut self, start: usize, length: usize) {
+        self.spans.insert(start, length);
+        self.largest.set(start, length);
+
+        if length < LONGEST_FILE {
+            self.short[length].insert(start);
+        } else {
+            self.smallest_long.set(start, length);
+        }
+    }
+
+    fn remove(&mut self, start: usize) -> usize {
+        let length = self.spans.remove(&start).unwrap();
+        self.largest.set(start, 0);
+
+        if length < LONGEST_FILE {
+            self.short[length].remove(&start);
+        } else {
+            self.smallest_long.set(start, usize::MAX);
+        }
+
+        length
+    }
+
+    // Takes block i out of the span it is in
+    fn occupy(&mut self, i: usize) {
+        let (&start, &length) = self.spans.range(..=i).next_back().unwrap();
+        self.remove(start);
+
+        if i > start {
+            self.insert(start, i - start);
+        }
+        if start + length > i + 1 {
+            self.insert(i + 1, start + length - i - 1);
+        }
+    }
+
+    // Adds block i, merging it with the spans right before and after it
+    fn release(&mut self, i: usize) {
+        let (mut start, mut length) = (i, 1);
+
+        if let Some((&s, &l)) = self.spans.range(..i).next_back() {
+            if s + l == i {
+                self.remove(s);
+                (start, length) = (s, l + 1);
+            }
+        }
+
+        if self.spans.contains_key(&(i + 1)) {
+            length += self.remove(i + 1);
+        }
+
+        self.insert(start, length);
+    }
+
+    // The start of the span a file of the given length goes in, out of the
+    // spans that start before limit
+    fn fit(&self, length: usize, limit: usize, fit: &Fit) -> Option<usize> {
+        match fit {
+            Fit::First => self.largest.find(limit, &|l| l >= length),
+            Fit::Worst => {
+                let largest = self.largest.before(limit);
+                if largest < length.max(1) {
+                    return None
+                }
+                self.largest.find(limit, &|l| l >= largest)
+            },
+            Fit::Best => {
+                for short in self.short.iter().skip(length.max(1)) {
+                    if let Some(&start) = short.first().filter(|&&s| s < limit) {
+                        return Some(start)
+                    }
+                }
+
+                let smallest = self.smallest_long.before(limit);
+                if smallest == usize::MAX {
+                    return None
+                }
+                self.smallest_long.find(limit, &|l| l <= smallest)
+            }
+        }
+    }
+}
+
+// Every block on the disk, with the id of the file it belongs to. The
+// blocks of every file and the free spans are kept up to date on every move.
+struct Disk {
+    blocks: Vec<Option<usize>>,
+    files: Vec<Vec<usize>>,
+    slots: Vec<usize>,
+    free: FreeSpans
+}
+
+impl Disk {
+    fn new(files: &Layout, spaces: &Layout) -> Disk {
+        let mut blocks = vec![];
+
+        for (id, &length) in files.iter().enumerate() {
+            blocks.extend(vec![Some(id); length]);
+            blocks.extend(vec![None; spaces.get(id).copied().unwrap_or(0)]);
+        }
+
+        let mut owned: Vec<Vec<usize>> = vec![vec![]; files.len()];
+        let mut slots = vec![0; blocks.len()];
+
+        for (i, owner) in blocks.iter().enumerate() {
+            if let Some(id) = owner {
+                slots[i] = owned[*id].len();
+                owned[*id].push(i);
+            }
+        }
+
+        let free = FreeSpans::new(&blocks);
+        Disk { blocks, files: owned, slots, free }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let (oa, ob) = (self.blocks[a], self.blocks[b]);
+        if oa == ob {
+            return
+        }
+
+        if let Some(id) = oa {
+            self.files[id][self.slots[a]] = b;
+        }
+        if let Some(id) = ob {
+            self.files[id][self.slots[b]] = a;
+        }
+
+        match (oa, ob) {
+            (None, _) => {
+                self.free.occupy(a);
+                self.free.release(b);
+            },
+            (_, None) => {
+                self.free.occupy(b);
+                self.free.release(a);
+            },
+            _ => {}
+        }
+
+        self.slots.swap(a, b);
+        self.blocks.swap(a, b);
+    }
+
+    // Moves all blocks of a file, in order, to the free blocks from start on
+    fn move_file(&mut self, id: usize, start: usize) {
+        let mut blocks = self.files[id].clone();
+        blocks.sort();
+
+        for (i, from) in blocks.into_iter().enumerate() {
+            self.swap(from, start + i);
+        }
+    }
+
+    // Runs of blocks with the same owner as (owner, start, length)
+    fn runs(&self) -> Vec<(Option<usize>, usize, usize)> {
+        let mut runs: Vec<(Option<usize>, usize, usize)> = vec![];
+
+        for (i, &owner) in self.blocks.iter().enumerate() {
+            match runs.last_mut() {
+                Some((o, _, length)) if *o == owner => *length += 1,
+                _ => runs.push((owner, i, 1))
+            }
+        }
+
+        runs
+    }
+
+    fn free_spans(&self) -> Vec<(usize, usize)> {
+        self.free.spans.iter().map(|(&start, &length)| (start, length)).collect()
+    }
+    fn checksum(&self) -> usize {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, owner)| i * owner.unwrap_or(0))
+            .sum()
+    }
+
+    // Ids past 35 don't fit in a single character and are drawn as #
+    fn render(&self) -> String {
+        self.blocks
+            .iter()
+            .map(|owner| match owner {
+                Some(id) => char::from_digit(*id as u32, 36).unwrap_or('#'),
+                None => '.'
+            })
+            .collect()
+    }
+
+    fn fragmentation(&self) -> Fragmentation {
+        let free: Vec<usize> = self.free_spans()
+            .iter()
+            .map(|&(_, length)| length)
+            .collect();
+
+        let mut fragments: Vec<usize> = vec![];
+        for (owner, _, _) in self.runs().iter() {
+            if let Some(id) = owner {
+                if *id >= fragments.len() {
+                    fragments.resize(id + 1, 0);
+                }
+                fragments[*id] += 1;
+            }
+        }
+
+        Fragmentation {
+            free_blocks: free.iter().sum(),
+            free_spans: free.len(),
+            largest_free_span: free.iter().copied().max().unwrap_or(0),
+            fragmented_files: fragments.iter().filter(|&&f| f > 1).count()
+        }
+    }
+}
+
+type OnMove<'a> = &'a mut dyn FnMut(&Disk);
+
+trait Strategy {
+    fn name(&self) -> &'static str;
+    fn compact(&self, disk: &mut Disk, on_move: OnMove);
+}
+
+// Moves single blocks from the end into the leftmost free block
+struct BlockMove;
+
+impl Strategy for BlockMove {
+    fn name(&self) -> &'static str {
+        "blocks"
+    }
+
+    fn compact(&self, disk: &mut Disk, on_move: OnMove) {
+        let (mut l, mut r) = (0, disk.blocks.len().saturating_sub(1));
+
+        while l < r {
+            if disk.blocks[l].is_some() {
+                l += 1;
+            } else if disk.blocks[r].is_none() {
+                r -= 1;
+            } else {
+                disk.swap(l, r);
+                on_move(disk);
+            }
+        }
+    }
+}
+
+enum Fit {
+    First,
+    Best,
+    Worst
+}
+
+enum FileOrder {
+    HighestId,
+    SmallestFirst
+}
+
+// Moves whole files to a free span left of them, if there is one
+struct FileMove {
+    name: &'static str,
+    fit: Fit,
+    order: FileOrder
+}
+
+impl Strategy for FileMove {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn compact(&self, disk: &mut Disk, on_move: OnMove) {
+        let mut files: Vec<(usize, usize)> = disk.files
+            .iter()
+            .enumerate()
+            .filter(|(_, blocks)| !blocks.is_empty())
+            .map(|(id, blocks)| (id, blocks.len()))
+            .collect();
+
+        match self.order {
+            FileOrder::HighestId => files.sort_by_key(|&(id, _)| Reverse(id)),
+            FileOrder::SmallestFirst => files.sort_by_key(|&(id, length)| (length, Reverse(id)))
+        }
+
+        for (id, length) in files {
+            let start = *disk.files[id].iter().min().unwrap();
+
+            if let Some(to) = disk.free.fit(length, start, &self.fit) {
+                disk.move_file(id, to);
+                on_move(disk);
+            }
+        }
+    }
+}
+
+// Packs every file to the left as one contiguous run, keeping the order in
+// which the files first appear. Blocks of files that are in the way get
+// swapped out and are put back together once it's their turn.
+struct Defragment;
+
+impl Strategy for Defragment {
+    fn name(&self) -> &'static str {
+        "defragment"
+    }
+
+    fn compact(&self, disk: &mut Disk, on_move: OnMove) {
+        let mut order: Vec<usize> = vec![];
+        let mut seen = vec![false; disk.files.len()];
+        for &owner in disk.blocks.iter().flatten() {
+            if !seen[owner] {
+                seen[owner] = true;
+                order.push(owner);
+            }
+        }
+
+        let mut position = 0;
+
+        for id in order {
+            let length = disk.files[id].len();
+            let target = position..(position + length);
+            position += length;
+
+            let mut outside: Vec<usize> = disk.files[id]
+                .iter()
+                .copied()
+                .filter(|i| !target.contains(i))
+                .collect();
+
+            if outside.is_empty() {
+                continue
+            }
+
+            outside.sort();
+            let inside: Vec<usize> = target
+                .filter(|&i| disk.blocks[i] != Some(id))
+                .collect();
+
+            for (&from, &to) in outside.iter().zip(inside.iter()) {
+                disk.swap(from, to);
+            }
+            on_move(disk);
+        }
+    }
+}
+
+fn strategies() -> Vec<Box<dyn Strategy>> {
+    vec![
+        Box::new(BlockMove),
+        Box::new(FileMove { name: "first-fit", fit: Fit::First, order: FileOrder::HighestId }),
+        Box::new(FileMove { name: "best-fit", fit: Fit::Best, order: FileOrder::HighestId }),
+        Box::new(FileMove { name: "worst-fit", fit: Fit::Worst, order: FileOrder::HighestId }),
+        Box::new(FileMove { name: "smallest-first", fit: Fit::First, order: FileOrder::SmallestFirst }),
+        Box::new(Defragment)
+    ]
+}
+
+#[derive(Debug, PartialEq)]
+struct Metrics {
+    checksum: usize,
+    fragmentation: Fragmentation,
+    moves: usize
+}
+
+fn evaluate(strategy: &dyn Strategy, files: &Layout, spaces: &Layout) -> Metrics {
+    let mut disk = Disk::new(files, spaces);
+    let mut moves = 0;
+
+    strategy.compact(&mut disk, &mut |_| moves += 1);
+
+    Metrics {
+        checksum: disk.checksum(),
+        fragmentation: disk.fragmentation(),
+        moves
+    }
+}
+
 fn main() {
-    let (mut files, mut spaces) = parse("input");
-    let mut f = files.clone();
-    println!("p1 {}", checksum(&mut files, &spaces));
-    println!("p2 {}", checksum_whole(&mut f, &mut spaces));
+    let (files, spaces) = parse("input");
+    println!("p1 {}", checksum(&files, &spaces));
+    println!("p2 {}", checksum_whole(&files, &spaces));
+
+    let Some(mode) = env::args().nth(1) else { return };
+
+    if mode == "compare" {
+        for strategy in strategies() {
+            println!("{} {:?}", strategy.name(), evaluate(strategy.as_ref(), &files, &spaces));
+        }
+        return
+    }
+
+    let strategy = strategies()
+        .into_iter()
+        .find(|s| s.name() == mode)
+        .unwrap_or_else(|| panic!("Invalid strategy {}", mode));
+    let mut disk = Disk::new(&files, &spaces);
+    println!("{}", disk.render());
+
+    strategy.compact(&mut disk, &mut |d| println!("{}", d.render()));
+
+    println!("checksum {}", disk.checksum());
+    println!("{:?}", disk.fragmentation());
 }
 
 fn parse(input: &'static str) -> DiskMap {
@@ -20,8 +532,7 @@ fn parse(input: &'static str) -> DiskMap {
     let mut spaces = vec![];
     let mut files = vec![];
 
-    for i in 0..diskmap.len() {
-        let c = diskmap.chars().nth(i).unwrap();
+    for (i, c) in diskmap.chars().enumerate() {
         let d = c.to_digit(10).unwrap() as usize;
 
         if i % 2 == 0 {
@@ -34,123 +545,237 @@ fn parse(input: &'static str) -> DiskMap {
     (files, spaces)
 }
 
-fn checksum(files: &mut Layout, spaces: &Layout) -> usize {
-    let mut compressed = vec![];
-    let mut findex = 0;
-    let mut frindex = files.len() - 1;
-    let mut sindex = 0;
-
-    loop {
-        let l = files[findex];
+// The checksum of a file with the given id, taking up length blocks from start
+fn span_checksum(id: usize, start: usize, length: usize) -> usize {
+    id * (length * start + (length * length.saturating_sub(1)) / 2)
+}
 
-        extend(&mut compressed, findex, l);
-        files[findex] = 0;
-        findex += 1;
+// Fills every gap from the left with blocks of the rightmost files, while
+// keeping count of the position instead of writing out the blocks.
+fn checksum(files: &Layout, spaces: &Layout) -> usize {
+    let mut remaining = files.clone();
+    let mut total = 0;
+    let mut position = 0;
+    let mut right = files.len() - 1;
 
-        if files.iter().all(|&fl| fl == 0) {
-            break;
+    for left in 0..files.len() {
+        if left > right {
+            break
         }
 
-        let sl = spaces[sindex];
-        let fl = files[frindex];
+        total += span_checksum(left, position, remaining[left]);
+        position += remaining[left];
+        remaining[left] = 0;
 
-        if sl >= fl {
-            let mut dl = sl;
+        let mut gap = spaces.get(left).copied().unwrap_or(0);
 
-            while frindex > 0 && dl > 0 {
-                let ffl = cmp::min(files[frindex], dl);
+        while gap > 0 && right > left {
+            let length = cmp::min(gap, remaining[right]);
 
-                extend(&mut compressed, frindex, ffl);
-                files[frindex] -= ffl;
-                dl -= ffl;
+            total += span_checksum(right, position, length);
+            position += length;
+            remaining[right] -= length;
+            gap -= length;
 
-                if files[frindex] == 0 {
-                    frindex -= 1;
-                }
+            if remaining[right] == 0 {
+                right -= 1;
             }
-        } else {
-            extend(&mut compressed, frindex, sl);
-            files[frindex] -= sl;
-        }
-        sindex += 1;
-
-        if files.iter().all(|&fl| fl == 0) {
-            break;
         }
     }
 
-    (0..compressed.len())
-        .map(|i| compressed[i] * i)
-        .sum()
-}
-
-fn extend(compressed: &mut Layout, n: usize, length: usize) {
-    for _ in 0..length {
-        compressed.push(n)
-    }
+    total
 }
 
 #[test]
 fn test_expand_compress_easy() {
-    let (mut files, spaces) = parse("1");
-    assert_eq!(checksum(&mut files, &spaces), 60);
+    let (files, spaces) = parse("1");
+    assert_eq!(checksum(&files, &spaces), 60);
 }
 
 #[test]
 fn test_expand_compress() {
-    let (mut files, spaces) = parse("2");
-    assert_eq!(checksum(&mut files, &spaces), 1928);
+    let (files, spaces) = parse("2");
+    assert_eq!(checksum(&files, &spaces), 1928);
 
-    let (mut files, spaces) = parse("3");
-    assert_eq!(checksum(&mut files, &spaces), 2132);
+    let (files, spaces) = parse("3");
+    assert_eq!(checksum(&files, &spaces), 2132);
 
-    let (mut files, spaces) = parse("4");
-    assert_eq!(checksum(&mut files, &spaces), 275);
+    let (files, spaces) = parse("4");
+    assert_eq!(checksum(&files, &spaces), 275);
 }
 
-fn checksum_whole(files: &mut Layout, spaces: &mut Layout) -> usize {
-    let mut compressed = vec![];
-    let mut sindex = 0;
-    let mut list = vec![];
+// Free spans are kept in one min-heap (by position) per span length, so the
+// leftmost span a file fits in is the best of the heap tops. Files are at
+// most 9 blocks long, so every longer span shares the last heap.
+fn checksum_whole(files: &Layout, spaces: &Layout) -> usize {
+    let mut spans: Vec<(usize, usize)> = vec![];
+    let mut starts = vec![0; files.len()];
+    let mut position = 0;
 
-    for id in (0..files.len()).rev() {
-        for i in 0..spaces.len() {
-            let fl = files[id];
-            if fl == 0 || spaces[i] < fl || id <= i {
-                continue
-            }
+    for (id, &length) in files.iter().enumerate() {
+        starts[id] = position;
+        position += length;
 
-            list.push((id, fl, i));
-            spaces[i] -= fl;
-            break;
+        let space = spaces.get(id).copied().unwrap_or(0);
+
+        // Spaces around an empty file make up one span
+        match spans.last_mut() {
+            Some((start, size)) if *start + *size == position => *size += space,
+            _ => spans.push((position, space))
         }
+        position += space;
     }
 
-    for findex in 0..files.len() {
-        let l = files[findex];
-        let mut t = findex;
-        if list.iter().any(|(id, _, _)| id == &findex) {
-            t = 0;
-        }
-        extend(&mut compressed, t, l);
+    let mut heaps: Vec<BinaryHeap<Reverse<(usize, usize)>>> = vec![BinaryHeap::new(); LONGEST_FILE + 1];
 
-        let sl = spaces.get(sindex).unwrap_or(&0);
-        for (a, b, _) in list.iter().filter(|&r| r.2 == sindex) {
-            extend(&mut compressed, *a, *b);
+    for (start, size) in spans {
+        if size > 0 {
+            heaps[size.min(LONGEST_FILE)].push(Reverse((start, size)));
         }
-        extend(&mut compressed, 0, *sl);
-        sindex += 1;
     }
 
-    (0..compressed.len())
-        .map(|i| compressed[i] * i)
-        .sum()
+    let mut total = 0;
+
+    for id in (0..files.len()).rev() {
+        let length = files[id];
+        let best = (length.max(1)..heaps.len())
+            .filter_map(|bucket| heaps[bucket].peek().map(|&Reverse(span)| (span, bucket)))
+            .filter(|&((start, _), _)| start < starts[id])
+            .min();
+
+        let start = match best {
+            Some(((start, size), bucket)) => {
+                heaps[bucket].pop();
+                if size > length {
+                    let rest = size - length;
+                    heaps[rest.min(LONGEST_FILE)].push(Reverse((start + length, rest)));
+                }
+                start
+            },
+            None => starts[id]
+        };
+
+        total += span_checksum(id, start, length);
+    }
+
+    total
 }
 
 #[test]
 fn test_expand_compress_whole() {
-    let (mut files, mut spaces) = parse("2");
-    assert_eq!(checksum_whole(&mut files, &mut spaces), 2858);
-    let (mut files, mut spaces) = parse("5");
-    assert_eq!(checksum_whole(&mut files, &mut spaces), 1715);
+    let (files, spaces) = parse("2");
+    assert_eq!(checksum_whole(&files, &spaces), 2858);
+    let (files, spaces) = parse("5");
+    assert_eq!(checksum_whole(&files, &spaces), 1715);
+    let (files, spaces) = parse("6");
+    assert_eq!(checksum_whole(&files, &spaces), 44687);
+
+    // One long span in front of many empty files
+    let mut files = vec![1];
+    let mut spaces = vec![9];
+    for _ in 0..20000 {
+        files.push(0);
+        spaces.push(9);
+    }
+    files.push(1);
+    assert_eq!(checksum_whole(&files, &spaces), 20001);
+
+    let mut disk = Disk::new(&files, &spaces);
+    strategies()[1].compact(&mut disk, &mut |_| {});
+    assert_eq!(disk.checksum(), 20001);
+}
+
+#[test]
+fn test_disk_compact_blocks() {
+    let (files, spaces) = parse("1");
+    let mut disk = Disk::new(&files, &spaces);
+    let mut steps = vec![disk.render()];
+
+    assert_eq!(disk.free_spans(), vec![(1, 2), (6, 4)]);
+
+    BlockMove.compact(&mut disk, &mut |d| steps.push(d.render()));
+
+    assert_eq!(steps, vec![
+        "0..111....22222",
+        "02.111....2222.",
+        "022111....222..",
+        "0221112...22...",
+        "02211122..2....",
+        "022111222......"
+    ]);
+    assert_eq!(disk.checksum(), 60);
+}
+
+#[test]
+fn test_disk_compact_files() {
+    let (files, spaces) = parse("2");
+    let strategy = &strategies()[1];
+    let mut disk = Disk::new(&files, &spaces);
+    let mut moves = 0;
+
+    strategy.compact(&mut disk, &mut |_| moves += 1);
+
+    assert_eq!(strategy.name(), "first-fit");
+    assert_eq!(moves, 4);
+    assert_eq!(disk.render(), "00992111777.44.333....5555.6666.....8888..");
+    assert_eq!(disk.checksum(), 2858);
+    assert_eq!(disk.fragmentation(), Fragmentation {
+        free_blocks: 14,
+        free_spans: 6,
+        largest_free_span: 5,
+        fragmented_files: 0
+    });
+
+    let mut disk = Disk::new(&files, &spaces);
+    BlockMove.compact(&mut disk, &mut |_| {});
+    assert_eq!(disk.render(), "0099811188827773336446555566..............");
+    assert_eq!(disk.fragmentation().fragmented_files, 2);
+}
+
+#[test]
+fn test_strategies() {
+    let (files, spaces) = parse("2");
+    let metrics: Vec<Metrics> = strategies()
+        .iter()
+        .map(|s| evaluate(s.as_ref(), &files, &spaces))
+        .collect();
+
+    assert_eq!(metrics[0].checksum, 1928);
+    assert_eq!(metrics[1].checksum, 2858);
+
+    let (small_files, small_spaces) = parse("7");
+    let renders: Vec<String> = strategies()[1..4]
+        .iter()
+        .map(|s| {
+            let mut disk = Disk::new(&small_files, &small_spaces);
+            s.compact(&mut disk, &mut |_| {});
+            disk.render()
+        })
+        .collect();
+    assert_eq!(renders, vec!["021....", "01...2.", "021...."]);
+
+    for strategy in strategies() {
+        let mut disk = Disk::new(&files, &spaces);
+        strategy.compact(&mut disk, &mut |_| {});
+
+        let free: Vec<(usize, usize)> = disk
+            .runs()
+            .into_iter()
+            .filter(|(owner, _, _)| owner.is_none())
+            .map(|(_, start, length)| (start, length))
+            .collect();
+        assert_eq!(disk.free_spans(), free);
+    }
+
+    let mut disk = Disk::new(&files, &spaces);
+    Defragment.compact(&mut disk, &mut |_| {});
+    assert_eq!(disk.render(), "0011123334455556666777888899..............");
+    assert_eq!(metrics[5].moves, 9);
+
+    let mut disk = Disk::new(&files, &spaces);
+    BlockMove.compact(&mut disk, &mut |_| {});
+    Defragment.compact(&mut disk, &mut |_| {});
+    assert_eq!(disk.render(), "0099888811127773336666445555..............");
+    assert_eq!(disk.fragmentation().fragmented_files, 0);
+    assert_eq!(disk.fragmentation().free_spans, 1);
 }
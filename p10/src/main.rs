@@ -1,5 +1,5 @@
 use std::fs;
-use std::collections::{HashSet, VecDeque};
+use std::env;
 
 const TRANSLATIONS: [Point; 4] = [
     (-1, 0), // TOP CENTRE
@@ -8,9 +8,101 @@ const TRANSLATIONS: [Point; 4] = [
     (1, 0),  // BOTTOM CENTRE
 ];
 
-type RawGrid = Vec<Vec<u8>>;
+type RawGrid = Vec<Vec<Option<u8>>>;
 type Point = (isize, isize);
-type PointValue = (isize, isize, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    // Exactly one higher
+    Climb,
+    // Between one and k higher
+    ClimbUpTo(u8),
+    // Exactly one lower
+    Descend
+}
+
+impl Step {
+    // "climb", "up:k" or "descend"
+    fn parse(step: &str) -> Step {
+        match step.split_once(":") {
+            None if step == "climb" => Step::Climb,
+            None if step == "descend" => Step::Descend,
+            Some(("up", k)) => Step::ClimbUpTo(k.parse().unwrap()),
+            _ => panic!("Invalid step {}", step)
+        }
+    }
+
+    fn allows(&self, from: u8, to: u8) -> bool {
+        match self {
+            Step::Climb => from + 1 == to,
+            Step::ClimbUpTo(k) => to > from && to - from <= *k,
+            Step::Descend => to + 1 == from
+        }
+    }
+
+    fn ascending(&self) -> bool {
+        *self != Step::Descend
+    }
+
+    // The largest height difference a single step can cover
+    fn reach(&self) -> usize {
+        match self {
+            Step::ClimbUpTo(k) => *k as usize,
+            _ => 1
+        }
+    }
+}
+
+struct Topography {
+    step: Step,
+    start: u8,
+    end: u8
+}
+
+impl Topography {
+    fn new(step: Step, start: u8, end: u8) -> Topography {
+        if start > 9 || end > 9 {
+            panic!("Heights go from 0 to 9, not {} to {}", start, end);
+        }
+
+        if start != end && step.ascending() != (start < end) {
+            panic!("Can not get from {} to {} with {:?}", start, end, step);
+        }
+
+        Topography { step, start, end }
+    }
+
+    fn hiking() -> Topography {
+        Topography::new(Step::Climb, 0, 9)
+    }
+
+    fn contains(&self, height: u8) -> bool {
+        height >= self.start.min(self.end) && height <= self.start.max(self.end)
+    }
+
+    // Every height a trail can pass, starting at the end
+    fn heights(&self) -> Vec<u8> {
+        if self.start <= self.end {
+            (self.start..=self.end).rev().collect()
+        } else {
+            (self.end..=self.start).collect()
+        }
+    }
+
+    // The neighbours a trail can step to from (y, x)
+    fn next(&self, grid: &Grid, y: isize, x: isize) -> Vec<Point> {
+        let Some(height) = grid.get(y, x) else { return vec![] };
+
+        TRANSLATIONS
+            .iter()
+            .map(|(dy, dx)| (y + dy, x + dx))
+            .filter(|&(ty, tx)| !grid.out_of_bounds(ty, tx))
+            .filter(|&(ty, tx)| {
+                grid.get(ty, tx).is_some_and(|h| self.contains(h) && self.step.allows(height, h))
+            })
+            .collect()
+    }
+}
 
 struct Grid {
     vector: RawGrid,
@@ -30,101 +122,245 @@ impl Grid {
         y < 0 || x < 0 || y >= self.ylen || x >= self.xlen
     }
 
-    fn get(&self, y: isize, x: isize) -> u8 {
+    // None for impassable cells
+    fn get(&self, y: isize, x: isize) -> Option<u8> {
         self.vector[y as usize][x as usize]
     }
+
+    fn index(&self, y: isize, x: isize) -> usize {
+        (y * self.xlen + x) as usize
+    }
 }
 
 fn main() {
     let grid = parse("input");
-    let (scores, ratings) = trailhead_totals(&grid);
+    let (scores, ratings) = trailhead_totals(&grid, &Topography::hiking());
     println!("p1 {}", scores);
     println!("p2 {}", ratings);
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let list = args.last().is_some_and(|a| a == "list");
+    if list {
+        args.pop();
+    }
+
+    let Some(step) = args.first().map(|a| Step::parse(a)) else { return };
+    let topography = match args[1..] {
+        [ref start, ref end] => Topography::new(step, start.parse().unwrap(), end.parse().unwrap()),
+        _ if step.ascending() => Topography::new(step, 0, 9),
+        _ => Topography::new(step, 9, 0)
+    };
+
+    for trailhead in trailheads(&grid, &topography) {
+        let (y, x) = trailhead.point;
+        println!("{},{} {} {}", y, x, trailhead.score, trailhead.rating);
+
+        if !list {
+            continue
+        }
+
+        for trail in trails(&grid, &topography, trailhead.point) {
+            let points: Vec<String> = trail
+                .iter()
+                .map(|(y, x)| format!("{},{}", y, x))
+                .collect();
+            println!("  {}", points.join(" "));
+        }
+    }
 }
 
+// Digits are heights, anything else is impassable
 fn parse(input: &'static str) -> Grid {
     let vector = fs::read_to_string(input).unwrap().lines().map(|line| {
-        line.chars().map(|i| i.to_digit(10).unwrap() as u8).collect()
+        line.chars().map(|i| i.to_digit(10).map(|d| d as u8)).collect()
     }).collect();
 
     Grid::new(vector)
 }
 
-fn trailhead_totals(grid: &Grid) -> (usize, usize) {
-    let starts = origins(grid);
-    let mut scores = 0;
-    let mut ratings = 0;
+fn trailhead_totals(grid: &Grid, topography: &Topography) -> (usize, usize) {
+    trailheads(grid, topography)
+        .iter()
+        .fold((0, 0), |(scores, ratings), t| (scores + t.score, ratings + t.rating))
+}
+
+// A fixed size set of summit indices
+#[derive(Clone)]
+struct Bitset(Vec<u64>);
+
+impl Bitset {
+    fn new(size: usize) -> Bitset {
+        Bitset(vec![0; size.div_ceil(64)])
+    }
 
-    for s in starts.iter() {
-        let (score, rating) = trailhead_total(grid, *s);
-        scores += score;
-        ratings += rating;
+    fn insert(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
     }
 
-    (scores, ratings)
+    fn union(&mut self, other: &Bitset) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= b;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
 }
 
-fn origins(grid: &Grid) -> Vec<PointValue> {
-    let mut starts = vec![];
+#[derive(Debug, PartialEq)]
+struct Trailhead {
+    point: Point,
+    score: usize,
+    rating: usize
+}
+
+// Works back from the summits one height at a time, so every cell gets the
+// set of summits it can reach and the amount of trails leading to them from
+// the neighbours it can step to. Only the sets of the heights a single step
+// can cover are kept.
+fn trailheads(grid: &Grid, topography: &Topography) -> Vec<Trailhead> {
+    let mut levels: Vec<Vec<Point>> = vec![vec![]; 10];
     for y in 0..grid.ylen {
         for x in 0..grid.xlen {
-            if grid.get(y, x) == 0 {
-                starts.push((y, x, 0));
+            if let Some(height) = grid.get(y, x) {
+                levels[height as usize].push((y, x));
             }
         }
     }
-    starts
-}
 
-fn trailhead_total(grid: &Grid, start: PointValue) -> (usize, usize) {
-    let mut set = HashSet::new();
-    let mut routes = 0;
-    let mut deq = VecDeque::new();
-    deq.push_back(start);
+    let heights = topography.heights();
+    let summits = &levels[topography.end as usize];
+    let cells = (grid.ylen * grid.xlen) as usize;
+    let mut reach: Vec<Option<Bitset>> = vec![None; cells];
+    let mut ratings = vec![0; cells];
 
-    while let Some((y, x, v)) = deq.pop_front() {
-        for (dy, dx) in &TRANSLATIONS {
-            let (ty, tx) = (y - dy, x - dx);
-
-            if grid.out_of_bounds(ty, tx) {
-                continue
-            }
+    for (i, &(y, x)) in summits.iter().enumerate() {
+        let mut set = Bitset::new(summits.len());
+        set.insert(i);
+        reach[grid.index(y, x)] = Some(set);
+        ratings[grid.index(y, x)] = 1;
+    }
 
-            let value = grid.get(ty, tx);
+    for i in 1..heights.len() {
+        for &(y, x) in levels[heights[i] as usize].iter() {
+            let mut set = Bitset::new(summits.len());
+            let mut rating = 0;
 
-            if v + 1 != value {
-                continue
+            for (ty, tx) in topography.next(grid, y, x) {
+                if let Some(ahead) = &reach[grid.index(ty, tx)] {
+                    set.union(ahead);
+                    rating += ratings[grid.index(ty, tx)];
+                }
             }
 
-            deq.push_back((ty, tx, value));
+            if rating > 0 {
+                reach[grid.index(y, x)] = Some(set);
+                ratings[grid.index(y, x)] = rating;
+            }
+        }
 
-            if value == 9 {
-                routes += 1;
-                set.insert((ty, tx));
-                continue
+        if i >= topography.step.reach() {
+            for &(y, x) in levels[heights[i - topography.step.reach()] as usize].iter() {
+                reach[grid.index(y, x)] = None;
             }
         }
     }
 
-    (set.len(), routes)
+    levels[topography.start as usize]
+        .iter()
+        .map(|&(y, x)| Trailhead {
+            point: (y, x),
+            score: reach[grid.index(y, x)].as_ref().map_or(0, |set| set.len()),
+            rating: ratings[grid.index(y, x)]
+        })
+        .collect()
+}
+
+#[test]
+fn test_trailheads() {
+    let grid = parse("2");
+    let trailheads = trailheads(&grid, &Topography::hiking());
+    let scores: Vec<usize> = trailheads.iter().map(|t| t.score).collect();
+    let ratings: Vec<usize> = trailheads.iter().map(|t| t.rating).collect();
+
+    assert_eq!(trailheads[0].point, (0, 2));
+    assert_eq!(scores, vec![5, 6, 5, 3, 1, 3, 5, 3, 5]);
+    assert_eq!(ratings, vec![20, 24, 10, 4, 1, 4, 5, 8, 5]);
 }
 
 #[test]
 fn test_trailhead_totals() {
     let grid = parse("1");
-    let (scores, _) = trailhead_totals(&grid);
+    let (scores, _) = trailhead_totals(&grid, &Topography::hiking());
     assert_eq!(scores, 1);
 
     let grid = parse("3");
-    let (scores, _) = trailhead_totals(&grid);
+    let (scores, _) = trailhead_totals(&grid, &Topography::hiking());
     assert_eq!(scores, 2);
 
     let grid = parse("4");
-    let (scores, _) = trailhead_totals(&grid);
+    let (scores, _) = trailhead_totals(&grid, &Topography::hiking());
     assert_eq!(scores, 4);
 
     let grid = parse("2");
-    let (scores, ratings) = trailhead_totals(&grid);
+    let (scores, ratings) = trailhead_totals(&grid, &Topography::hiking());
     assert_eq!(scores, 36);
     assert_eq!(ratings, 81);
 }
+
+// Every trail from the given trailhead, in order
+fn trails(grid: &Grid, topography: &Topography, from: Point) -> Vec<Vec<Point>> {
+    let mut found = vec![];
+    let mut stack = vec![vec![from]];
+
+    while let Some(trail) = stack.pop() {
+        let &(y, x) = trail.last().unwrap();
+
+        if grid.get(y, x) == Some(topography.end) {
+            found.push(trail);
+            continue
+        }
+
+        for point in topography.next(grid, y, x) {
+            let mut next = trail.clone();
+            next.push(point);
+            stack.push(next);
+        }
+    }
+
+    found.sort();
+    found
+}
+
+#[test]
+fn test_topography() {
+    let grid = parse("5");
+    assert_eq!(trailhead_totals(&grid, &Topography::hiking()), (4, 13));
+
+    let grid = parse("2");
+    let descending = Topography::new(Step::parse("descend"), 9, 0);
+    let (_, ratings) = trailhead_totals(&grid, &descending);
+    assert_eq!(ratings, 81);
+
+    let grid = parse("6");
+    let steep = Topography::new(Step::parse("up:2"), 0, 5);
+    assert_eq!(trailheads(&grid, &steep), vec![Trailhead { point: (0, 0), score: 1, rating: 2 }]);
+    assert_eq!(trails(&grid, &steep, (0, 0)), vec![
+        vec![(0, 0), (0, 1), (0, 2), (0, 3), (1, 3)],
+        vec![(0, 0), (0, 1), (0, 2), (1, 2), (1, 3)]
+    ]);
+    assert_eq!(trailhead_totals(&grid, &Topography::new(Step::Climb, 0, 5)), (0, 0));
+
+    let grid = parse("2");
+    let short = Topography::new(Step::parse("up:3"), 2, 7);
+    for trailhead in trailheads(&grid, &short) {
+        assert_eq!(trails(&grid, &short, trailhead.point).len(), trailhead.rating);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Heights go from 0 to 9")]
+fn test_height_out_of_range() {
+    Topography::new(Step::Climb, 0, 12);
+}
@@ -1,14 +1,207 @@
 use std::fs;
+use std::env;
+use digits::digit_length;
 use std::collections::HashMap;
 
 type Stones = Vec<u64>;
 
+// The puzzle's own rules, in the rules file format
+const BLINK_RULES: &str = "\
+value = 0 -> 1
+digits % 2 = 0 -> split 2
+* -> value * 2024
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subject {
+    Value,
+    Digits
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+// subject [% modulo] comparison operand, e.g. "digits % 2 = 0"
+#[derive(Debug, PartialEq)]
+struct Condition {
+    subject: Subject,
+    modulo: Option<u64>,
+    comparison: Comparison,
+    operand: u64
+}
+
+impl Condition {
+    fn parse(condition: &str) -> Condition {
+        let tokens: Vec<&str> = condition.split_whitespace().collect();
+        let (subject, modulo, comparison, operand) = match tokens[..] {
+            [subject, comparison, operand] => (subject, None, comparison, operand),
+            [subject, "%", modulo, comparison, operand] => (subject, Some(modulo), comparison, operand),
+            _ => panic!("Invalid condition {}", condition)
+        };
+
+        let subject = match subject {
+            "value" => Subject::Value,
+            "digits" => Subject::Digits,
+            _ => panic!("Invalid subject {}", subject)
+        };
+        let comparison = match comparison {
+            "=" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => panic!("Invalid comparison {}", comparison)
+        };
+
+        Condition {
+            subject,
+            modulo: modulo.map(|m| match m.parse() {
+                Ok(0) | Err(_) => panic!("Invalid condition {}", condition),
+                Ok(m) => m
+            }),
+            comparison,
+            operand: operand.parse().unwrap()
+        }
+    }
+
+    fn holds(&self, stone: u64) -> bool {
+        let mut n = match self.subject {
+            Subject::Value => stone,
+            Subject::Digits => digit_length(stone) as u64
+        };
+
+        if let Some(m) = self.modulo {
+            n %= m;
+        }
+
+        match self.comparison {
+            Comparison::Eq => n == self.operand,
+            Comparison::Ne => n != self.operand,
+            Comparison::Lt => n < self.operand,
+            Comparison::Le => n <= self.operand,
+            Comparison::Gt => n > self.operand,
+            Comparison::Ge => n >= self.operand
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Output {
+    // value * multiplier + addend, a constant when the multiplier is 0
+    Linear(u64, u64),
+    // Cuts the digits into k equally long stones, the first one taking
+    // any digits left over
+    Split(u32)
+}
+
+impl Output {
+    // "7", "value", "value * 2024", "value + 1", "value * 3 + 1" or "split 2"
+    fn parse(output: &str) -> Output {
+        let tokens: Vec<&str> = output.split_whitespace().collect();
+        let number = |n: &str| n.parse::<u64>().unwrap_or_else(|_| panic!("Invalid output {}", output));
+
+        match tokens[..] {
+            ["split", k] if number(k) > 0 => Output::Split(number(k) as u32),
+            ["value"] => Output::Linear(1, 0),
+            ["value", "*", m] => Output::Linear(number(m), 0),
+            ["value", "+", a] => Output::Linear(1, number(a)),
+            ["value", "*", m, "+", a] => Output::Linear(number(m), number(a)),
+            [n] => Output::Linear(0, number(n)),
+            _ => panic!("Invalid output {}", output)
+        }
+    }
+
+    fn apply(&self, stone: u64, emit: &mut impl FnMut(u64)) {
+        match *self {
+            Output::Linear(m, a) => {
+                let next = stone
+                    .checked_mul(m)
+                    .and_then(|n| n.checked_add(a))
+                    .unwrap_or_else(|| panic!("Stone {} overflows", stone));
+                emit(next);
+            },
+            Output::Split(k) => {
+                let length = digit_length(stone);
+                let part = (length / k).max(1);
+                let parts = length.div_ceil(part).min(k);
+                let mut rest = stone;
+
+                let mut cut = vec![];
+                for _ in 1..parts {
+                    let p = 10_u64.pow(part);
+                    cut.push(rest % p);
+                    rest /= p;
+                }
+                cut.push(rest);
+
+                for n in cut.into_iter().rev() {
+                    emit(n);
+                }
+            }
+        }
+    }
+}
+
+// conditions -> outputs, where conditions are joined by "and" or are a
+// single "*", and outputs are separated by ","
+#[derive(Debug, PartialEq)]
+struct Rule {
+    conditions: Vec<Condition>,
+    outputs: Vec<Output>
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Rule {
+        let (conditions, outputs) = rule
+            .split_once("->")
+            .unwrap_or_else(|| panic!("Invalid rule {}", rule));
+
+        let conditions = match conditions.trim() {
+            "*" => vec![],
+            conditions => conditions.split(" and ").map(Condition::parse).collect()
+        };
+
+        Rule {
+            conditions,
+            outputs: outputs.split(",").map(Output::parse).collect()
+        }
+    }
+
+    fn matches(&self, stone: u64) -> bool {
+        self.conditions.iter().all(|c| c.holds(stone))
+    }
+}
+
+// One rule per line, blank lines and lines starting with # are skipped
+fn parse_rules(rules: &str) -> Vec<Rule> {
+    rules
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("#"))
+        .map(Rule::parse)
+        .collect()
+}
+
 fn main() {
     let stones = parse("input");
-    let stone_count = count_stones(&stones, 25);
+    let rules = parse_rules(BLINK_RULES);
+    let stone_count = count_stones(&stones, 25, &rules);
     println!("p1 {}", stone_count);
-    let stone_count = count_stones(&stones, 75);
+    let stone_count = count_stones(&stones, 75, &rules);
     println!("p2 {}", stone_count);
+
+    let Some(file) = env::args().nth(1) else { return };
+    let rules = parse_rules(&fs::read_to_string(file).unwrap());
+    let blinks = env::args().nth(2).map_or(75, |b| b.parse().unwrap());
+    println!("{}", count_stones(&stones, blinks, &rules));
 }
 
 fn parse(input: &'static str) -> Stones {
@@ -20,31 +213,24 @@ fn parse(input: &'static str) -> Stones {
         .collect()
 }
 
-fn count_stones(stones: &Stones, blinks: u16) -> usize {
+// Every stone is changed by the first rule it matches, and stays the same if
+// there is none.
+fn count_stones(stones: &Stones, blinks: u16, rules: &[Rule]) -> usize {
     let mut map:HashMap<u64, usize> = HashMap::new();
 
     for stone in stones {
-        map.insert(*stone, 1);
+        *map.entry(*stone).or_default() += 1;
     }
 
     for _ in 0..blinks {
         let mut cache: HashMap<u64, usize> = HashMap::new();
 
         for (&stone, &count) in map.iter() {
-            if stone == 0 {
-                *cache.entry(1).or_default() += count;
-            } else {
-                let dl = digit_length(stone);
-                if dl % 2 == 0 {
-                    let k = 10_u64.pow(dl / 2);
-                    let l = stone / k;
-                    let r = stone - (l * k);
-
-                    *cache.entry(l).or_default() += count;
-                    *cache.entry(r).or_default() += count;
-                } else {
-                    *cache.entry(stone * 2024).or_default() += count;
-                }
+            let mut emit = |next: u64| *cache.entry(next).or_default() += count;
+
+            match rules.iter().find(|rule| rule.matches(stone)) {
+                Some(rule) => rule.outputs.iter().for_each(|o| o.apply(stone, &mut emit)),
+                None => emit(stone)
             }
         }
         map = cache;
@@ -53,15 +239,51 @@ fn count_stones(stones: &Stones, blinks: u16) -> usize {
     map.values().sum()
 }
 
-fn digit_length(stone: u64) -> u32 {
-    ((stone as f64).log10().floor() + 1.0) as u32
+#[test]
+fn test_count_stones() {
+    let stones = parse("1");
+    let rules = parse_rules(BLINK_RULES);
+    assert_eq!(count_stones(&stones, 1, &rules), 3);
+    assert_eq!(count_stones(&stones, 2, &rules), 4);
+    assert_eq!(count_stones(&stones, 6, &rules), 22);
+    assert_eq!(count_stones(&stones, 25, &rules), 55312);
 }
 
 #[test]
-fn test_count_stones() {
+fn test_rules() {
+    assert_eq!(Rule::parse("value >= 10 and digits % 3 = 1 -> value + 1, 7"), Rule {
+        conditions: vec![
+            Condition { subject: Subject::Value, modulo: None, comparison: Comparison::Ge, operand: 10 },
+            Condition { subject: Subject::Digits, modulo: Some(3), comparison: Comparison::Eq, operand: 1 }
+        ],
+        outputs: vec![Output::Linear(1, 1), Output::Linear(0, 7)]
+    });
+
+    let split = |stone: u64, k: u32| {
+        let mut parts = vec![];
+        Output::Split(k).apply(stone, &mut |n| parts.push(n));
+        parts
+    };
+    assert_eq!(split(1000, 2), vec![10, 0]);
+    assert_eq!(split(123456, 3), vec![12, 34, 56]);
+    assert_eq!(split(12345, 2), vec![123, 45]);
+    assert_eq!(split(7, 3), vec![7]);
+
     let stones = parse("1");
-    assert_eq!(count_stones(&stones, 1), 3);
-    assert_eq!(count_stones(&stones, 2), 4);
-    assert_eq!(count_stones(&stones, 6), 22);
-    assert_eq!(count_stones(&stones, 25), 55312);
+    let rules = parse_rules(&fs::read_to_string("2").unwrap());
+    assert_eq!(count_stones(&stones, 1, &rules), 4);
+    assert_eq!(count_stones(&stones, 2, &rules), 4);
+    assert_eq!(count_stones(&stones, 3, &rules), 6);
+}
+
+#[test]
+#[should_panic(expected = "Invalid output")]
+fn test_split_zero() {
+    Rule::parse("* -> split 0");
+}
+
+#[test]
+#[should_panic(expected = "Invalid condition")]
+fn test_modulo_zero() {
+    Rule::parse("digits % 0 = 1 -> 1");
 }